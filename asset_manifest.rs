@@ -0,0 +1,134 @@
+use crate::{cache_groups::manifest_path, client_paths, ClientPaths, Sunfish};
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeMap, path::Path};
+
+/// The path of the asset manifest in the output, written by `build`. Its `urls` map the path of each asset relative to the crate, such as `images/logo.svg`, to the URL it is served at, along with `bundles/<name>` for each bundle and `js/<package name>.js` and `js/<package name>_bg.wasm` for each client crate. Its `content_hashes` records whether those URLs are named by the hash of their contents.
+pub const ASSET_MANIFEST_PATH: &str = "manifest.json";
+
+/// Collects the URL of each asset while building.
+pub(crate) struct AssetManifest {
+	/// Whether the assets, bundles, and client crates are named by the hash of their contents, as set by `BuildOptions::content_hashes`.
+	pub content_hashes: bool,
+	pub urls: BTreeMap<String, String>,
+}
+
+impl AssetManifest {
+	pub fn new(content_hashes: bool) -> AssetManifest {
+		AssetManifest {
+			content_hashes,
+			urls: BTreeMap::new(),
+		}
+	}
+
+	/// Record that the asset at `path`, relative to the crate for files, is written to `output_path` relative to the output.
+	pub fn add(&mut self, path: &Path, output_path: &Path) {
+		if let (Some(path), Some(output_path)) = (manifest_path(path), manifest_path(output_path)) {
+			self.urls.insert(path, format!("/{}", output_path));
+		}
+	}
+
+	/// Whether `path`, relative to the output, is the path of a bundle. Bundles are always named by the hash of their contents.
+	pub fn is_bundle_path(&self, path: &Path) -> bool {
+		let url = match manifest_path(path) {
+			Some(path) => format!("/{}", path),
+			None => return false,
+		};
+		self.urls
+			.iter()
+			.any(|(name, bundle_url)| name.starts_with("bundles/") && *bundle_url == url)
+	}
+
+	/// Write the manifest to `output_dir`. Paths are sorted and always use `/` as the separator, so the manifest is byte for byte the same across runs and platforms for the same inputs.
+	pub fn write(self, output_dir: &Path) -> Result<()> {
+		let manifest = serde_json::json!({
+			"content_hashes": self.content_hashes,
+			"urls": self.urls,
+		});
+		let mut manifest = serde_json::to_string_pretty(&manifest)?;
+		manifest.push('\n');
+		// Only write the manifest if it changed, so that included directories are not needlessly rebuilt.
+		let manifest_path = output_dir.join(ASSET_MANIFEST_PATH);
+		if std::fs::read_to_string(&manifest_path).ok().as_deref() != Some(manifest.as_str()) {
+			std::fs::write(manifest_path, manifest)?;
+		}
+		Ok(())
+	}
+}
+
+impl Sunfish {
+	/// Get the URL the asset at `path` relative to the crate, such as `images/logo.svg`, is served at, as recorded in the asset manifest. Returns `None` if there is no such asset.
+	pub fn asset_url(&self, path: &str) -> Option<&str> {
+		self.asset_manifest()?
+			.urls
+			.get(path.trim_start_matches('/'))
+			.map(String::as_str)
+	}
+
+	/// Get the URL the bundle named `name` in `BuildOptions::bundles` is served at, as recorded in the asset manifest.
+	pub fn bundle_url(&self, name: &str) -> Option<&str> {
+		self.asset_manifest()?
+			.urls
+			.get(&format!("bundles/{}", name))
+			.map(String::as_str)
+	}
+
+	/// Get the paths the client crate with the package name `crate_name` is served at, as recorded in the asset manifest.
+	pub fn client_paths(&self, crate_name: &str) -> Option<ClientPaths> {
+		let manifest = &self.asset_manifest()?.urls;
+		Some(ClientPaths {
+			path_js: manifest.get(&format!("js/{}.js", crate_name))?.clone(),
+			path_wasm: manifest.get(&format!("js/{}_bg.wasm", crate_name))?.clone(),
+		})
+	}
+
+	/// Get the paths of the client crate for the route that `path` matches, so that each page only loads its own route's client instead of every client in the site. Returns `None` if no route matches or the route has no client crate. The paths come from the asset manifest if there is one, and otherwise are the path based names from `client_paths`.
+	pub fn client_paths_for_route(&self, path: &str) -> Option<ClientPaths> {
+		let client_crate_name = self
+			.routes
+			.iter()
+			.find(|route_initializer| route_initializer.matches(path))?
+			.client_crate_name?;
+		self.client_paths(client_crate_name)
+			.or_else(|| Some(client_paths(client_crate_name)))
+	}
+
+	/// Read and parse the asset manifest the first time it is needed.
+	pub(crate) fn asset_manifest(&self) -> Option<&AssetManifest> {
+		self.asset_manifest
+			.get_or_init(|| {
+				let manifest = self
+					.output
+					.read(Path::new(ASSET_MANIFEST_PATH))
+					.and_then(|manifest| manifest.map(|manifest| manifest.data()).transpose());
+				let manifest = match manifest {
+					Ok(Some(manifest)) => manifest,
+					Ok(None) => return None,
+					Err(error) => {
+						tracing::error!(?error, "failed to read the asset manifest");
+						return None;
+					}
+				};
+				match parse_asset_manifest(manifest.as_ref()) {
+					Ok(manifest) => Some(manifest),
+					Err(error) => {
+						tracing::error!(?error, "failed to parse the asset manifest");
+						None
+					}
+				}
+			})
+			.as_ref()
+	}
+}
+
+fn parse_asset_manifest(manifest: &[u8]) -> Result<AssetManifest> {
+	let mut manifest: serde_json::Value = serde_json::from_slice(manifest)?;
+	let content_hashes = manifest
+		.get("content_hashes")
+		.and_then(|content_hashes| content_hashes.as_bool())
+		.ok_or_else(|| anyhow!("expected content_hashes to be a bool"))?;
+	let urls = serde_json::from_value(manifest["urls"].take())?;
+	Ok(AssetManifest {
+		content_hashes,
+		urls,
+	})
+}
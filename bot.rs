@@ -0,0 +1,109 @@
+/// Substrings of the `User-Agent` header sent by well known crawlers and link preview bots, in lowercase.
+pub const DEFAULT_BOT_USER_AGENTS: &[&str] = &[
+	"applebot",
+	"baiduspider",
+	"bingbot",
+	"discordbot",
+	"duckduckbot",
+	"embedly",
+	"facebookexternalhit",
+	"googlebot",
+	"linkedinbot",
+	"pinterestbot",
+	"slackbot",
+	"slurp",
+	"telegrambot",
+	"twitterbot",
+	"whatsapp",
+	"yandexbot",
+];
+
+/// Whether the request's `User-Agent` contains one of `DEFAULT_BOT_USER_AGENTS`, ignoring case.
+pub fn is_bot(request: &http::Request<hyper::Body>) -> bool {
+	is_bot_with_user_agents(request, DEFAULT_BOT_USER_AGENTS)
+}
+
+/// Whether the request's `User-Agent` contains one of `bot_user_agents`, ignoring case.
+pub fn is_bot_with_user_agents(
+	request: &http::Request<hyper::Body>,
+	bot_user_agents: &[impl AsRef<str>],
+) -> bool {
+	let user_agent = match request
+		.headers()
+		.get(http::header::USER_AGENT)
+		.and_then(|user_agent| user_agent.to_str().ok())
+	{
+		Some(user_agent) => user_agent.to_ascii_lowercase(),
+		None => return false,
+	};
+	bot_user_agents
+		.iter()
+		.any(|bot_user_agent| user_agent.contains(&bot_user_agent.as_ref().to_ascii_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn request(user_agent: Option<&str>) -> http::Request<hyper::Body> {
+		let mut request = http::Request::builder();
+		if let Some(user_agent) = user_agent {
+			request = request.header(http::header::USER_AGENT, user_agent);
+		}
+		request.body(hyper::Body::empty()).unwrap()
+	}
+
+	#[test]
+	fn known_bots_are_bots() {
+		for user_agent in [
+			"Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+			"Mozilla/5.0 (Linux; Android 6.0.1; Nexus 5X Build/MMB29P) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.6045.199 Mobile Safari/537.36 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+			"Mozilla/5.0 (compatible; bingbot/2.0; +http://www.bing.com/bingbot.htm)",
+			"Mozilla/5.0 (compatible; Yahoo! Slurp; http://help.yahoo.com/help/us/ysearch/slurp)",
+			"DuckDuckBot/1.1; (+http://duckduckgo.com/duckduckbot.html)",
+			"Mozilla/5.0 (compatible; Baiduspider/2.0; +http://www.baidu.com/search/spider.html)",
+			"Mozilla/5.0 (compatible; YandexBot/3.0; +http://yandex.com/bots)",
+			"Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/13.1.1 Safari/605.1.15 (Applebot/0.1; +http://www.apple.com/go/applebot)",
+			"facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)",
+			"Twitterbot/1.0",
+			"LinkedInBot/1.0 (compatible; Mozilla/5.0; Apache-HttpClient +http://www.linkedin.com)",
+			"Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+			"Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)",
+			"TelegramBot (like TwitterBot)",
+			"WhatsApp/2.23.20.0",
+			"Pinterestbot/1.0 (+http://www.pinterest.com/bot.html)",
+			"Mozilla/5.0 (compatible; Embedly/0.2; +http://support.embed.ly/)",
+		] {
+			assert!(is_bot(&request(Some(user_agent))), "{}", user_agent);
+		}
+	}
+
+	#[test]
+	fn browsers_are_not_bots() {
+		for user_agent in [
+			"Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+			"Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:121.0) Gecko/20100101 Firefox/121.0",
+			"Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Safari/605.1.15",
+			"Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1",
+			"Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.6099.144 Mobile Safari/537.36",
+			"Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.0.0",
+			"curl/8.4.0",
+		] {
+			assert!(!is_bot(&request(Some(user_agent))), "{}", user_agent);
+		}
+		assert!(!is_bot(&request(None)));
+	}
+
+	#[test]
+	fn custom_user_agents_replace_the_defaults() {
+		let bot_user_agents = ["MyCrawler"];
+		assert!(is_bot_with_user_agents(
+			&request(Some("mycrawler/1.0")),
+			&bot_user_agents
+		));
+		assert!(!is_bot_with_user_agents(
+			&request(Some("Mozilla/5.0 (compatible; Googlebot/2.1)")),
+			&bot_user_agents
+		));
+	}
+}
@@ -1,14 +1,39 @@
-use crate::hash;
+use crate::{
+	asset_manifest::{AssetManifest, ASSET_MANIFEST_PATH},
+	bundle_path,
+	cache_groups::{CacheGroups, CACHE_GROUPS_PATH},
+	hash, is_compressible,
+};
 use anyhow::{anyhow, Result};
+use flate2::{write::GzEncoder, Compression};
 use ignore::Walk;
 use rayon::prelude::*;
-use std::path::PathBuf;
+use std::{
+	collections::BTreeMap,
+	io::Write,
+	path::{Path, PathBuf},
+};
 
 pub struct BuildOptions {
 	pub workspace_path: PathBuf,
 	pub crate_path: PathBuf,
 	pub crate_out_dir: PathBuf,
 	pub css_paths: Vec<PathBuf>,
+	/// Maps the name of each cache group to glob patterns for the files in it. A pattern may match a file's path relative to `crate_path`, such as `static/theme/**`, or its path in the output, such as `styles.css`. The groups are written to a manifest in the output and resolved by `Sunfish::cache_group_urls`.
+	pub cache_groups: BTreeMap<String, Vec<String>>,
+	/// Bundles to concatenate into single assets, named by the hash of their contents and served at the path `Sunfish::bundle_url` returns.
+	pub bundles: Vec<Bundle>,
+	/// Whether assets, bundles, and client crates are named by the hash of their contents instead of the hash of their paths, so that changing a file changes its URL. Pages must then get URLs from `Sunfish::asset_url` and `Sunfish::client_paths`, which read the asset manifest, instead of from `asset_path` and `client_paths`. Bundles are always named by the hash of their contents. Existing deployments can keep the default path based names until their pages have switched. Files from previous builds that are no longer referenced are removed from the output. Only with content hashes are assets and clients sent with an `immutable` cache policy, since path based names keep their URL when their contents change.
+	pub content_hashes: bool,
+	/// Whether to write a gzipped copy next to each compressible file in the output, such as `app.js.gz` next to `app.js`, for `Sunfish` to serve to clients that accept gzip. Only gzip is supported. No Brotli copies are written, and `Sunfish` never sends Brotli encoded responses.
+	pub precompress: bool,
+}
+
+pub struct Bundle {
+	/// The name of the bundle, such as `app.css`. Its extension is the extension of the bundled asset.
+	pub name: String,
+	/// The paths of the files to concatenate, relative to `crate_path`, in order.
+	pub paths: Vec<PathBuf>,
 }
 
 pub fn build(options: BuildOptions) -> Result<()> {
@@ -19,7 +44,9 @@ pub fn build(options: BuildOptions) -> Result<()> {
 	std::fs::create_dir_all(&output_dir).unwrap();
 	std::fs::create_dir_all(&assets_dir).unwrap();
 	std::fs::create_dir_all(&js_dir).unwrap();
-	// Build client crates.
+	let mut cache_groups = CacheGroups::new(&options.cache_groups)?;
+	let mut asset_manifest = AssetManifest::new(options.content_hashes);
+	// Build client crates. Each gets its own js and wasm pair, so a page only loads the client of its route.
 	let mut client_crate_manifest_paths = Vec::new();
 	for entry in Walk::new(options.crate_path.join("routes")) {
 		let entry = entry.unwrap();
@@ -34,7 +61,7 @@ pub fn build(options: BuildOptions) -> Result<()> {
 		.iter()
 		.map(|client_crate_manifest_path| {
 			let client_crate_manifest =
-				std::fs::read_to_string(&options.workspace_path.join(client_crate_manifest_path))?;
+				std::fs::read_to_string(options.workspace_path.join(client_crate_manifest_path))?;
 			let client_crate_manifest: toml::Value = toml::from_str(&client_crate_manifest)?;
 			let client_crate_package_name = client_crate_manifest
 				.as_table()
@@ -51,15 +78,19 @@ pub fn build(options: BuildOptions) -> Result<()> {
 			Ok(client_crate_package_name)
 		})
 		.collect::<Result<Vec<_>>>()?;
-	client_crate_package_names
+	let client_crate_hashes = client_crate_package_names
 		.par_iter()
-		.for_each(|client_crate_package_name| {
-			let hash = hash(client_crate_package_name);
+		.map(|client_crate_package_name| {
 			let input_path = std::env::var(format!(
 				"CARGO_BIN_FILE_{}",
 				client_crate_package_name.to_uppercase()
 			))
 			.unwrap();
+			let hash = if options.content_hashes {
+				hash(std::fs::read(&input_path).unwrap())
+			} else {
+				hash(client_crate_package_name)
+			};
 			let output_path = js_dir.join(format!("{}_bg.wasm", hash));
 			// Do not re-run wasm-bindgen if the output wasm exists and is not older than the input wasm.
 			let input_metadata = std::fs::metadata(&input_path).unwrap();
@@ -67,7 +98,7 @@ pub fn build(options: BuildOptions) -> Result<()> {
 			if let Ok(output_wasm_metadata) = std::fs::metadata(&output_path) {
 				let output_modified_time = output_wasm_metadata.modified().unwrap();
 				if input_modified_time <= output_modified_time {
-					return;
+					return hash;
 				}
 			}
 			wasm_bindgen_cli_support::Bindgen::new()
@@ -82,7 +113,22 @@ pub fn build(options: BuildOptions) -> Result<()> {
 				.generate(&js_dir)
 				.map_err(|error| anyhow!(error))
 				.unwrap();
-		});
+			hash
+		})
+		.collect::<Vec<_>>();
+	for (client_crate_package_name, hash) in client_crate_package_names
+		.iter()
+		.zip(client_crate_hashes.iter())
+	{
+		asset_manifest.add(
+			Path::new(&format!("js/{}.js", client_crate_package_name)),
+			Path::new(&format!("js/{}.js", hash)),
+		);
+		asset_manifest.add(
+			Path::new(&format!("js/{}_bg.wasm", client_crate_package_name)),
+			Path::new(&format!("js/{}_bg.wasm", hash)),
+		);
+	}
 	// Collect CSS.
 	let mut css = String::new();
 	for dir in options.css_paths {
@@ -104,7 +150,11 @@ pub fn build(options: BuildOptions) -> Result<()> {
 			continue;
 		}
 		let output_path = output_dir.join(input_path.strip_prefix(&static_dir).unwrap());
-		let input_metadata = std::fs::metadata(&input_path).unwrap();
+		cache_groups.add(
+			input_path.strip_prefix(&options.crate_path).unwrap(),
+			output_path.strip_prefix(&output_dir).unwrap(),
+		);
+		let input_metadata = std::fs::metadata(input_path).unwrap();
 		let input_modified_time = input_metadata.modified().unwrap();
 		if let Ok(output_metadata) = std::fs::metadata(&output_path) {
 			let output_modified_time = output_metadata.modified().unwrap();
@@ -116,6 +166,7 @@ pub fn build(options: BuildOptions) -> Result<()> {
 		std::fs::copy(input_path, output_path).unwrap();
 	}
 	// Copy assets.
+	let mut asset_paths = Vec::new();
 	let asset_extensions = &["gif", "jpg", "png", "svg", "woff2"];
 	for entry in Walk::new(&options.crate_path) {
 		let entry = entry.unwrap();
@@ -131,10 +182,23 @@ pub fn build(options: BuildOptions) -> Result<()> {
 		if !asset_extensions.contains(&extension) {
 			continue;
 		}
-		let asset_path = input_path.strip_prefix(&options.workspace_path).unwrap();
-		let hash = hash(asset_path.to_str().unwrap().as_bytes());
-		let output_path = assets_dir.join(&format!("{}.{}", hash, extension));
-		let input_metadata = std::fs::metadata(&input_path).unwrap();
+		let hash = if options.content_hashes {
+			hash(std::fs::read(input_path)?)
+		} else {
+			let asset_path = input_path.strip_prefix(&options.workspace_path).unwrap();
+			hash(asset_path.to_str().unwrap().as_bytes())
+		};
+		let output_path = assets_dir.join(format!("{}.{}", hash, extension));
+		asset_paths.push(output_path.clone());
+		cache_groups.add(
+			input_path.strip_prefix(&options.crate_path).unwrap(),
+			output_path.strip_prefix(&output_dir).unwrap(),
+		);
+		asset_manifest.add(
+			input_path.strip_prefix(&options.crate_path).unwrap(),
+			output_path.strip_prefix(&output_dir).unwrap(),
+		);
+		let input_metadata = std::fs::metadata(input_path).unwrap();
 		let input_modified_time = input_metadata.modified().unwrap();
 		if let Ok(output_metadata) = std::fs::metadata(&output_path) {
 			let output_modified_time = output_metadata.modified().unwrap();
@@ -145,5 +209,144 @@ pub fn build(options: BuildOptions) -> Result<()> {
 		std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
 		std::fs::copy(input_path, output_path).unwrap();
 	}
+	// Concatenate bundles.
+	for bundle in options.bundles.iter() {
+		let mut data = Vec::new();
+		for path in bundle.paths.iter() {
+			let path = options.crate_path.join(path);
+			let file_data = std::fs::read(&path)
+				.map_err(|error| anyhow!("failed to read {}: {}", path.display(), error))?;
+			data.extend_from_slice(&file_data);
+			if !data.ends_with(b"\n") {
+				data.push(b'\n');
+			}
+		}
+		let output_path =
+			output_dir.join(bundle_path(&bundle.name, &data).strip_prefix('/').unwrap());
+		asset_manifest.add(
+			&Path::new("bundles").join(&bundle.name),
+			output_path.strip_prefix(&output_dir).unwrap(),
+		);
+		asset_paths.push(output_path.clone());
+		// Only write the bundle if it changed, so that included directories are not needlessly rebuilt.
+		if std::fs::read(&output_path).ok().as_deref() != Some(data.as_slice()) {
+			std::fs::write(&output_path, data)?;
+		}
+	}
+	asset_manifest.write(&output_dir)?;
+	// Remove the assets and client files of previous builds, whose names changed along with their contents. Bundles are always named by their contents, so the assets directory is always cleaned.
+	remove_stale_files(&assets_dir, |path| {
+		asset_paths.iter().any(|asset_path| asset_path == path)
+	})?;
+	if options.content_hashes {
+		remove_stale_files(&js_dir, |path| {
+			let file_name = path
+				.file_name()
+				.and_then(|file_name| file_name.to_str())
+				.unwrap_or("");
+			client_crate_hashes
+				.iter()
+				.any(|hash| file_name.starts_with(hash.as_str()))
+		})?;
+	}
+	// Write gzipped copies of compressible files.
+	if options.precompress {
+		precompress(&output_dir)?;
+	}
+	// Add the files in the output whose output paths match a cache group and write the manifest.
+	for entry in Walk::new(&output_dir) {
+		let entry = entry.unwrap();
+		if !entry.path().is_file() {
+			continue;
+		}
+		let output_path = entry.path().strip_prefix(&output_dir).unwrap();
+		if output_path == Path::new(CACHE_GROUPS_PATH)
+			|| output_path == Path::new(ASSET_MANIFEST_PATH)
+		{
+			continue;
+		}
+		cache_groups.add(output_path, output_path);
+	}
+	cache_groups.write(&output_dir)?;
+	Ok(())
+}
+
+/// Remove the files directly in `dir` that `is_current` rejects, along with their gzipped copies.
+fn remove_stale_files(dir: &Path, is_current: impl Fn(&Path) -> bool) -> Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		let current_path = match path.extension().and_then(|extension| extension.to_str()) {
+			Some("gz") => path.with_extension(""),
+			_ => path.clone(),
+		};
+		if path.is_file() && !is_current(&current_path) {
+			std::fs::remove_file(&path)?;
+		}
+	}
 	Ok(())
 }
+
+/// Write a gzipped copy next to each compressible file in `output_dir` that does not have an up to date one.
+fn precompress(output_dir: &Path) -> Result<()> {
+	for entry in Walk::new(output_dir) {
+		let entry = entry?;
+		let input_path = entry.path();
+		if !input_path.is_file() {
+			continue;
+		}
+		let extension = match input_path.extension().and_then(|e| e.to_str()) {
+			Some(extension) => extension,
+			None => continue,
+		};
+		if !is_compressible(input_path) {
+			continue;
+		}
+		let output_path = input_path.with_extension(format!("{}.gz", extension));
+		let input_modified_time = std::fs::metadata(input_path)?.modified()?;
+		if let Ok(output_metadata) = std::fs::metadata(&output_path) {
+			if input_modified_time <= output_metadata.modified()? {
+				continue;
+			}
+		}
+		let data = std::fs::read(input_path)?;
+		let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+		encoder.write_all(&data)?;
+		std::fs::write(&output_path, encoder.finish()?)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stale_files_and_their_gzipped_copies_are_removed() {
+		let dir = tempfile::tempdir().unwrap();
+		let dir = dir.path();
+		for name in [
+			"current-1234.css",
+			"current-1234.css.gz",
+			"stale-5678.css",
+			"stale-5678.css.gz",
+			"orphan-9abc.js.gz",
+		] {
+			std::fs::write(dir.join(name), name).unwrap();
+		}
+		std::fs::create_dir(dir.join("nested")).unwrap();
+		std::fs::write(dir.join("nested").join("stale.css"), "").unwrap();
+		let current = [dir.join("current-1234.css")];
+		remove_stale_files(dir, |path| current.iter().any(|current| current == path)).unwrap();
+		let mut remaining = std::fs::read_dir(dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		remaining.sort();
+		assert_eq!(
+			remaining,
+			vec!["current-1234.css", "current-1234.css.gz", "nested"]
+		);
+		// Subdirectories are left alone.
+		assert!(dir.join("nested").join("stale.css").exists());
+	}
+}
@@ -0,0 +1,218 @@
+use crate::Sunfish;
+use anyhow::Result;
+use ignore::overrides::{Override, OverrideBuilder};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	path::Path,
+};
+
+/// The path of the cache group manifest in the output, written by `build` when `BuildOptions::cache_groups` is not empty.
+pub const CACHE_GROUPS_PATH: &str = "cache_groups.toml";
+
+/// Collects the output paths that belong to each cache group while building.
+pub(crate) struct CacheGroups {
+	groups: Vec<(String, Override, BTreeSet<String>)>,
+}
+
+impl CacheGroups {
+	/// Compile the glob patterns for each group. Patterns use gitignore syntax.
+	pub fn new(cache_groups: &BTreeMap<String, Vec<String>>) -> Result<CacheGroups> {
+		let mut groups = Vec::new();
+		for (name, patterns) in cache_groups.iter() {
+			let mut builder = OverrideBuilder::new("");
+			for pattern in patterns {
+				builder.add(pattern)?;
+			}
+			groups.push((name.to_owned(), builder.build()?, BTreeSet::new()));
+		}
+		Ok(CacheGroups { groups })
+	}
+
+	/// Add `output_path` to every group with a pattern that matches `path`.
+	pub fn add(&mut self, path: &Path, output_path: &Path) {
+		let output_path = match manifest_path(output_path) {
+			Some(output_path) => output_path,
+			None => return,
+		};
+		for (_, patterns, output_paths) in self.groups.iter_mut() {
+			if patterns.matched(path, false).is_whitelist() {
+				output_paths.insert(output_path.clone());
+			}
+		}
+	}
+
+	/// Write the manifest to `output_dir`, or do nothing if no groups were declared. Groups and the paths in each group are sorted and paths always use `/` as the separator, so the manifest is byte for byte the same across runs and platforms for the same inputs.
+	pub fn write(self, output_dir: &Path) -> Result<()> {
+		if self.groups.is_empty() {
+			return Ok(());
+		}
+		let manifest = self
+			.groups
+			.into_iter()
+			.map(|(name, _, output_paths)| (name, output_paths.into_iter().collect::<Vec<_>>()))
+			.collect::<BTreeMap<_, _>>();
+		let manifest = toml::to_string(&manifest)?;
+		// Only write the manifest if it changed, so that included directories are not needlessly rebuilt.
+		let manifest_path = output_dir.join(CACHE_GROUPS_PATH);
+		if std::fs::read_to_string(&manifest_path).ok().as_deref() != Some(manifest.as_str()) {
+			std::fs::write(manifest_path, manifest)?;
+		}
+		Ok(())
+	}
+}
+
+/// Convert a relative path to a string with `/` separators, or `None` if it is not valid UTF-8.
+pub(crate) fn manifest_path(path: &Path) -> Option<String> {
+	let components = path
+		.components()
+		.map(|component| component.as_os_str().to_str())
+		.collect::<Option<Vec<_>>>()?;
+	Some(components.join("/"))
+}
+
+impl Sunfish {
+	/// Get the URLs of every file in the cache group named `name`, for example to purge them from a CDN together. If content addressed assets are enabled, each file's `/cas/` URL is included as well. Returns an empty list if there is no such group.
+	pub fn cache_group_urls(&self, name: &str) -> Vec<String> {
+		let manifest = self
+			.output
+			.read(Path::new(CACHE_GROUPS_PATH))
+			.and_then(|manifest| manifest.map(|manifest| manifest.data()).transpose());
+		let manifest = match manifest {
+			Ok(Some(manifest)) => manifest,
+			Ok(None) => return Vec::new(),
+			Err(error) => {
+				tracing::error!(?error, "failed to read the cache group manifest");
+				return Vec::new();
+			}
+		};
+		let manifest: BTreeMap<String, Vec<String>> = match toml::from_slice(manifest.as_ref()) {
+			Ok(manifest) => manifest,
+			Err(error) => {
+				tracing::error!(?error, "failed to parse the cache group manifest");
+				return Vec::new();
+			}
+		};
+		let paths = match manifest.get(name) {
+			Some(paths) => paths,
+			None => return Vec::new(),
+		};
+		let mut urls = Vec::new();
+		for path in paths {
+			urls.push(format!("/{}", path));
+			if !self.content_addressed_assets {
+				continue;
+			}
+			let full_hash = self
+				.output
+				.read(Path::new(path))
+				.ok()
+				.flatten()
+				.and_then(|file| match file.full_hash() {
+					Some(full_hash) => Some(full_hash.to_owned()),
+					None => file.data().ok().map(crate::full_hash),
+				});
+			if let Some(full_hash) = full_hash {
+				urls.push(format!("/cas/{}", full_hash));
+			}
+		}
+		urls
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cache_groups(groups: &[(&str, &[&str])]) -> CacheGroups {
+		let groups = groups
+			.iter()
+			.map(|(name, patterns)| {
+				let patterns = patterns.iter().map(|pattern| pattern.to_string()).collect();
+				(name.to_string(), patterns)
+			})
+			.collect();
+		CacheGroups::new(&groups).unwrap()
+	}
+
+	fn groups(cache_groups: &CacheGroups) -> Vec<(&str, Vec<&str>)> {
+		cache_groups
+			.groups
+			.iter()
+			.map(|(name, _, output_paths)| {
+				let output_paths = output_paths.iter().map(|path| path.as_str()).collect();
+				(name.as_str(), output_paths)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn paths_are_added_to_every_matching_group() {
+		let mut cache_groups = cache_groups(&[
+			("styles", &["*.css"]),
+			("blog", &["blog/**", "!blog/drafts/**"]),
+			("everything", &["*"]),
+		]);
+		for (path, output_path) in [
+			("blog/post.css", "assets/post-1234.css"),
+			("blog/post.md", "blog/post/index.html"),
+			("blog/drafts/draft.md", "blog/drafts/draft/index.html"),
+			("index.js", "js/index-5678.js"),
+		] {
+			cache_groups.add(Path::new(path), Path::new(output_path));
+		}
+		assert_eq!(
+			groups(&cache_groups),
+			vec![
+				("blog", vec!["assets/post-1234.css", "blog/post/index.html"]),
+				(
+					"everything",
+					vec![
+						"assets/post-1234.css",
+						"blog/drafts/draft/index.html",
+						"blog/post/index.html",
+						"js/index-5678.js",
+					]
+				),
+				("styles", vec!["assets/post-1234.css"]),
+			]
+		);
+	}
+
+	#[test]
+	fn the_manifest_is_sorted_and_only_rewritten_when_it_changes() {
+		let output_dir = tempfile::tempdir().unwrap();
+		let manifest_path = output_dir.path().join(CACHE_GROUPS_PATH);
+		let build = || {
+			let mut cache_groups = cache_groups(&[("b", &["*.js"]), ("a", &["*.css"])]);
+			cache_groups.add(Path::new("z.css"), &Path::new("assets").join("z.css"));
+			cache_groups.add(Path::new("y.css"), &Path::new("assets").join("y.css"));
+			cache_groups.add(Path::new("x.js"), Path::new("x.js"));
+			cache_groups.write(output_dir.path()).unwrap();
+		};
+		build();
+		assert_eq!(
+			std::fs::read_to_string(&manifest_path).unwrap(),
+			"a = [\"assets/y.css\", \"assets/z.css\"]\nb = [\"x.js\"]\n"
+		);
+		let modified = std::fs::metadata(&manifest_path)
+			.unwrap()
+			.modified()
+			.unwrap();
+		std::thread::sleep(std::time::Duration::from_millis(10));
+		build();
+		assert_eq!(
+			std::fs::metadata(&manifest_path)
+				.unwrap()
+				.modified()
+				.unwrap(),
+			modified
+		);
+	}
+
+	#[test]
+	fn no_manifest_is_written_without_groups() {
+		let output_dir = tempfile::tempdir().unwrap();
+		cache_groups(&[]).write(output_dir.path()).unwrap();
+		assert!(!output_dir.path().join(CACHE_GROUPS_PATH).exists());
+	}
+}
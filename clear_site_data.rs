@@ -0,0 +1,43 @@
+/// A type of data that a `Clear-Site-Data` header asks the browser to clear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearSiteDataScope {
+	/// The HTTP cache and other cached data, such as prerendered pages.
+	Cache,
+	/// All cookies, including those set for subdomains.
+	Cookies,
+	/// Local storage, session storage, IndexedDB, service worker registrations, and similar storage.
+	Storage,
+	/// Reload every browsing context for the origin.
+	ExecutionContexts,
+	/// Every type of data.
+	All,
+}
+
+impl ClearSiteDataScope {
+	fn directive(&self) -> &'static str {
+		match self {
+			ClearSiteDataScope::Cache => "\"cache\"",
+			ClearSiteDataScope::Cookies => "\"cookies\"",
+			ClearSiteDataScope::Storage => "\"storage\"",
+			ClearSiteDataScope::ExecutionContexts => "\"executionContexts\"",
+			ClearSiteDataScope::All => "\"*\"",
+		}
+	}
+}
+
+/// Build a `200` response with a `Clear-Site-Data` header for `scopes`, for example from a logout route.
+///
+/// The browser clears the data for the whole origin, not just the path that sent the header, so every tab and every other app served from the origin is signed out and loses its cached and stored data. Browsers only honor the header over HTTPS.
+pub fn clear_site_data_response(scopes: &[ClearSiteDataScope]) -> http::Response<hyper::Body> {
+	let clear_site_data = scopes
+		.iter()
+		.map(ClearSiteDataScope::directive)
+		.collect::<Vec<_>>()
+		.join(", ");
+	http::Response::builder()
+		.status(http::StatusCode::OK)
+		.header("clear-site-data", clear_site_data)
+		.header(http::header::CACHE_CONTROL, "no-store")
+		.body(hyper::Body::empty())
+		.unwrap()
+}
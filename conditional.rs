@@ -0,0 +1,178 @@
+use std::time::SystemTime;
+
+/// Turn a successful response to a `GET` or `HEAD` request into a `304 Not Modified` if the request's `If-None-Match` or `If-Modified-Since` header shows that the client's cached copy is still fresh.
+pub fn apply_conditional(
+	method: &http::Method,
+	request_headers: &http::HeaderMap,
+	response: http::Response<hyper::Body>,
+) -> http::Response<hyper::Body> {
+	if method != http::Method::GET && method != http::Method::HEAD {
+		return response;
+	}
+	if response.status() != http::StatusCode::OK {
+		return response;
+	}
+	if !is_not_modified(request_headers, response.headers()) {
+		return response;
+	}
+	let (mut parts, _) = response.into_parts();
+	parts.status = http::StatusCode::NOT_MODIFIED;
+	parts.headers.remove(http::header::CONTENT_LENGTH);
+	http::Response::from_parts(parts, hyper::Body::empty())
+}
+
+fn is_not_modified(request_headers: &http::HeaderMap, response_headers: &http::HeaderMap) -> bool {
+	// If-None-Match takes precedence over If-Modified-Since.
+	if let Some(if_none_match) = request_headers.get(http::header::IF_NONE_MATCH) {
+		let etag = match response_headers
+			.get(http::header::ETAG)
+			.and_then(|etag| etag.to_str().ok())
+		{
+			Some(etag) => etag,
+			None => return false,
+		};
+		let if_none_match = match if_none_match.to_str() {
+			Ok(if_none_match) => if_none_match,
+			Err(_) => return false,
+		};
+		return etag_matches(if_none_match, etag);
+	}
+	if let Some(if_modified_since) = request_headers.get(http::header::IF_MODIFIED_SINCE) {
+		let last_modified = match response_headers
+			.get(http::header::LAST_MODIFIED)
+			.and_then(parse_http_date)
+		{
+			Some(last_modified) => last_modified,
+			None => return false,
+		};
+		let if_modified_since = match parse_http_date(if_modified_since) {
+			Some(if_modified_since) => if_modified_since,
+			None => return false,
+		};
+		return last_modified <= if_modified_since;
+	}
+	false
+}
+
+/// Compare an `If-None-Match` header value against an `ETag` using the weak comparison function.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+	let etag = normalize_etag(etag);
+	if_none_match
+		.split(',')
+		.map(|candidate| candidate.trim())
+		.any(|candidate| candidate == "*" || normalize_etag(candidate) == etag)
+}
+
+fn normalize_etag(etag: &str) -> &str {
+	let etag = etag.trim();
+	let etag = etag.strip_prefix("W/").unwrap_or(etag);
+	etag.trim_matches('"')
+}
+
+fn parse_http_date(value: &http::HeaderValue) -> Option<SystemTime> {
+	httpdate::parse_http_date(value.to_str().ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+	const EARLIER: &str = "Tue, 20 Oct 2015 07:28:00 GMT";
+	const LATER: &str = "Thu, 22 Oct 2015 07:28:00 GMT";
+
+	fn respond(
+		method: http::Method,
+		request_headers: &[(&str, &str)],
+	) -> http::Response<hyper::Body> {
+		let mut headers = http::HeaderMap::new();
+		for (name, value) in request_headers {
+			headers.append(
+				http::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+				value.parse().unwrap(),
+			);
+		}
+		let response = http::Response::builder()
+			.header(http::header::ETAG, "\"abc\"")
+			.header(http::header::LAST_MODIFIED, LAST_MODIFIED)
+			.header(http::header::CONTENT_LENGTH, "5")
+			.body(hyper::Body::from("hello"))
+			.unwrap();
+		apply_conditional(&method, &headers, response)
+	}
+
+	#[test]
+	fn etag_matches_table() {
+		let cases = [
+			("\"abc\"", "\"abc\"", true),
+			("W/\"abc\"", "\"abc\"", true),
+			("\"abc\"", "W/\"abc\"", true),
+			("W/\"abc\"", "W/\"abc\"", true),
+			("*", "\"abc\"", true),
+			("\"x\", \"abc\"", "\"abc\"", true),
+			("\"x\",W/\"abc\"", "\"abc\"", true),
+			("\"abcd\"", "\"abc\"", false),
+			("\"x\", \"y\"", "\"abc\"", false),
+		];
+		for (if_none_match, etag, expected) in cases {
+			assert_eq!(
+				etag_matches(if_none_match, etag),
+				expected,
+				"{} vs {}",
+				if_none_match,
+				etag
+			);
+		}
+	}
+
+	#[test]
+	fn matching_etags_are_not_modified() {
+		let response = respond(http::Method::GET, &[("if-none-match", "W/\"abc\"")]);
+		assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+		assert!(response
+			.headers()
+			.get(http::header::CONTENT_LENGTH)
+			.is_none());
+		assert_eq!(response.headers()[http::header::ETAG], "\"abc\"");
+	}
+
+	#[test]
+	fn if_none_match_takes_precedence_over_if_modified_since() {
+		// A stale ETag wins over a fresh date.
+		let response = respond(
+			http::Method::GET,
+			&[("if-none-match", "\"other\""), ("if-modified-since", LATER)],
+		);
+		assert_eq!(response.status(), http::StatusCode::OK);
+		// A fresh ETag wins over a stale date.
+		let response = respond(
+			http::Method::GET,
+			&[("if-none-match", "\"abc\""), ("if-modified-since", EARLIER)],
+		);
+		assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+	}
+
+	#[test]
+	fn if_modified_since_compares_against_last_modified() {
+		for (if_modified_since, expected) in [
+			(LAST_MODIFIED, http::StatusCode::NOT_MODIFIED),
+			(LATER, http::StatusCode::NOT_MODIFIED),
+			(EARLIER, http::StatusCode::OK),
+			("not a date", http::StatusCode::OK),
+		] {
+			let response = respond(
+				http::Method::GET,
+				&[("if-modified-since", if_modified_since)],
+			);
+			assert_eq!(response.status(), expected, "{}", if_modified_since);
+		}
+	}
+
+	#[test]
+	fn only_gets_and_heads_are_conditional() {
+		let response = respond(http::Method::HEAD, &[("if-none-match", "\"abc\"")]);
+		assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+		let response = respond(http::Method::POST, &[("if-none-match", "\"abc\"")]);
+		assert_eq!(response.status(), http::StatusCode::OK);
+	}
+}
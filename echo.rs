@@ -0,0 +1,48 @@
+use crate::percent_decode;
+use anyhow::Result;
+use std::fmt::Write;
+
+/// The path of the echo route, which is only served in debug builds.
+pub const ECHO_PATH: &str = "/__sunfish_echo";
+
+/// Respond with a plain text description of the request's method, URI, headers, query parameters, and body, to help debug what a client is actually sending.
+pub async fn echo(request: &mut http::Request<hyper::Body>) -> Result<http::Response<hyper::Body>> {
+	let mut text = String::new();
+	writeln!(
+		text,
+		"{} {} {:?}",
+		request.method(),
+		request.uri(),
+		request.version()
+	)?;
+	writeln!(text)?;
+	writeln!(text, "headers:")?;
+	for (name, value) in request.headers() {
+		writeln!(
+			text,
+			"\t{}: {}",
+			name,
+			String::from_utf8_lossy(value.as_bytes())
+		)?;
+	}
+	writeln!(text)?;
+	writeln!(text, "query:")?;
+	let query = request.uri().query().unwrap_or("");
+	for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+		let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+		let decode = |value: &str| {
+			percent_decode(&value.replace('+', " "), true).unwrap_or_else(|| value.to_owned())
+		};
+		writeln!(text, "\t{} = {}", decode(name), decode(value))?;
+	}
+	writeln!(text)?;
+	let body = hyper::body::to_bytes(std::mem::take(request.body_mut())).await?;
+	writeln!(text, "body ({} bytes):", body.len())?;
+	text.push_str(&String::from_utf8_lossy(&body));
+	let response = http::Response::builder()
+		.status(http::StatusCode::OK)
+		.header(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+		.body(hyper::Body::from(text))
+		.unwrap();
+	Ok(response)
+}
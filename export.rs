@@ -0,0 +1,972 @@
+use crate::{include_dir::PathFilter, Redirect, Route, RouteOptions, Sunfish};
+use anyhow::{anyhow, Context, Result};
+use ignore::Walk;
+use rayon::prelude::*;
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	io::Write,
+	path::{Path, PathBuf},
+};
+
+/// The name of the optional config file in `out_dir` that `export` reads options from. Its keys are `mode` (`"clean"`, `"merge"`, or `"incremental"`), `report_orphans`, `base_url`, `sitemap_format` (`"plain"`, `"plain_and_gzip"`, or `"gzip"`), `redirect_files` (a list of `"netlify"` and `"vercel"`), `headers_file`, `url_style` (`"html"` or `"trailing_slash"`), and `ignore` (a list of globs). Unknown keys are an error. Options set in `ExportOptions` take precedence over the file.
+pub const EXPORT_CONFIG_FILE_NAME: &str = "sunfish.export.toml";
+
+#[derive(Default)]
+pub struct ExportOptions {
+	/// Whether to clear `dist_path` before exporting. Defaults to `ExportMode::Clean`.
+	pub mode: Option<ExportMode>,
+	/// In `ExportMode::Merge` and `ExportMode::Incremental`, log each file in `dist_path` that export did not write.
+	pub report_orphans: Option<bool>,
+	/// The URL the exported site will be served from, for example `https://example.com`. Defaults to `Sunfish::base_url`. When set, a `sitemap.xml` listing every exported page is written, along with a `robots.txt` that points to it.
+	pub base_url: Option<String>,
+	/// Which sitemap files to write. Defaults to `SitemapFormat::Plain`.
+	pub sitemap_format: Option<SitemapFormat>,
+	/// Maps each logical page to its translations, as a map from `hreflang` value, such as `en` or `x-default`, to page path. When a base URL is set, every exported page in a group gets a `<link rel="alternate" hreflang="...">` element for each page in the group, including itself.
+	pub hreflang_alternates: Option<BTreeMap<String, BTreeMap<String, String>>>,
+	/// Whether to also list each page's `hreflang` alternates in the sitemap. Defaults to `false`.
+	pub sitemap_hreflang: Option<bool>,
+	/// The most URLs to list in one sitemap. Defaults to `SITEMAP_MAX_URLS`. If the pages do not fit in one sitemap, they are split across `sitemap-1.xml`, `sitemap-2.xml`, and so on, and `robots.txt` points to a `sitemap_index.xml` that references them.
+	pub sitemap_max_urls: Option<usize>,
+	/// The largest size in bytes of one sitemap before compression. Defaults to `SITEMAP_MAX_BYTES`.
+	pub sitemap_max_bytes: Option<usize>,
+	/// Which redirect files to write the redirects registered with `Sunfish::with_redirect` to. Defaults to none. A redirect file copied from the output is extended rather than replaced.
+	pub redirect_files: Option<Vec<RedirectFile>>,
+	/// Whether to write a `_headers` file, which Netlify and Cloudflare Pages read, giving each exported file the `Content-Type` that `Sunfish` would serve it with, including types registered with `Sunfish::with_content_type`. Defaults to `false`.
+	pub headers_file: Option<bool>,
+	/// Which file each page is written to. Defaults to `UrlStyle::Html`.
+	pub url_style: Option<UrlStyle>,
+	/// Globs in gitignore syntax, relative to the output, of files not to copy to `dist_path`.
+	pub ignore: Option<Vec<String>>,
+}
+
+/// The most URLs the sitemap protocol allows in one sitemap.
+pub const SITEMAP_MAX_URLS: usize = 50_000;
+
+/// The largest size in bytes the sitemap protocol allows for one uncompressed sitemap.
+pub const SITEMAP_MAX_BYTES: usize = 52_428_800;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportMode {
+	/// Delete `dist_path` and recreate it.
+	Clean,
+	/// Write and overwrite only the files export produces, leaving any others in `dist_path` intact.
+	Merge,
+	/// Like `Merge`, but leave files whose contents did not change untouched, so their modification times are preserved and tools like rsync only upload the files that changed.
+	Incremental,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SitemapFormat {
+	/// Write `sitemap.xml`.
+	Plain,
+	/// Write both `sitemap.xml` and `sitemap.xml.gz`.
+	PlainAndGzip,
+	/// Write only `sitemap.xml.gz`.
+	Gzip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UrlStyle {
+	/// Write the page at `/about` to `about.html`, which hosts serve at `/about`.
+	Html,
+	/// Write the page at `/about` to `about/index.html`, which hosts serve at `/about/`. The sitemap lists pages with a trailing slash.
+	TrailingSlash,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectFile {
+	/// Write `_redirects`, which Netlify and Cloudflare Pages read.
+	Netlify,
+	/// Write the `redirects` of `vercel.json`.
+	Vercel,
+}
+
+impl Sunfish {
+	pub fn export(&self, out_dir: &Path, dist_path: &Path) -> Result<()> {
+		self.export_with_options(out_dir, dist_path, ExportOptions::default())
+	}
+
+	pub fn export_with_options(
+		&self,
+		out_dir: &Path,
+		dist_path: &Path,
+		options: ExportOptions,
+	) -> Result<()> {
+		let options = options.merge(read_config(out_dir)?);
+		let output_path = out_dir.join("output");
+		let mode = options.mode.unwrap_or(ExportMode::Clean);
+		// Create a new directory at dist_path, or reuse the existing one when merging.
+		if mode == ExportMode::Clean && std::fs::metadata(dist_path).is_ok() {
+			std::fs::remove_dir_all(dist_path)
+				.with_context(|| format!("failed to remove {}", dist_path.display()))?;
+		}
+		std::fs::create_dir_all(dist_path)
+			.with_context(|| format!("failed to create {}", dist_path.display()))?;
+		let mut written_paths = BTreeSet::new();
+		let ignore = options
+			.ignore
+			.as_deref()
+			.unwrap_or_default()
+			.iter()
+			.map(String::as_str)
+			.collect::<Vec<_>>();
+		let filter = PathFilter::new(&output_path, &[], &ignore)
+			.with_context(|| format!("invalid ignore pattern in {:?}", ignore))?;
+		let url_style = options.url_style.unwrap_or(UrlStyle::Html);
+		// Copy the contents of the out_dir to the dist_path.
+		for entry in Walk::new(&output_path) {
+			let entry = entry.map_err(|error| match walk_error_path(&error) {
+				Some(path) => anyhow!("failed to read {}: {}", path.display(), error),
+				None => anyhow!(
+					"failed to read an entry in {}: {}",
+					output_path.display(),
+					error
+				),
+			})?;
+			let input_path = entry.path();
+			// Read the metadata through symlinks, so that a broken one is reported instead of skipped.
+			let metadata = std::fs::metadata(input_path)
+				.with_context(|| format!("failed to read {}", input_path.display()))?;
+			if !metadata.is_file() {
+				continue;
+			}
+			let path = input_path.strip_prefix(&output_path)?;
+			if !filter.is_included(path) {
+				continue;
+			}
+			let output_path = dist_path.join(path);
+			create_parent_dir(&output_path)?;
+			if mode == ExportMode::Incremental {
+				let contents = std::fs::read(input_path)
+					.with_context(|| format!("failed to read {}", input_path.display()))?;
+				write_file(&output_path, &contents, mode)?;
+			} else {
+				std::fs::copy(input_path, &output_path).with_context(|| {
+					format!(
+						"failed to copy {} to {}",
+						input_path.display(),
+						output_path.display()
+					)
+				})?;
+			}
+			written_paths.insert(output_path);
+		}
+		let base_url = options
+			.base_url
+			.as_deref()
+			.or(self.base_url.as_deref())
+			.map(|base_url| base_url.trim_end_matches('/'));
+		let alternates = alternates_by_path(options.hreflang_alternates.as_ref());
+		// Collect the pages of static routes and dynamic routes that opted in to prerendering.
+		let routes = self
+			.routes
+			.iter()
+			.map(|route_initializer| (route_initializer, (route_initializer.init)()))
+			.collect::<Vec<_>>();
+		let mut pages = Vec::new();
+		for (route_initializer, route) in routes.iter() {
+			match route {
+				Route::Static {
+					paths,
+					handler,
+					options,
+				} => {
+					let paths = paths
+						.as_ref()
+						.map(|paths| paths())
+						.unwrap_or_else(|| vec![route_initializer.path_with_placeholders.clone()]);
+					for path in paths {
+						pages.push(Page {
+							path,
+							render: Render::Handler(handler.as_ref()),
+							options,
+						});
+					}
+				}
+				Route::Dynamic {
+					prerender: Some(prerender),
+					options,
+					..
+				} => {
+					for path in (prerender.paths)() {
+						let render = match &prerender.render {
+							Some(render) => Render::Handler(render.as_ref()),
+							None => Render::Request,
+						};
+						pages.push(Page {
+							path,
+							render,
+							options,
+						});
+					}
+				}
+				Route::Dynamic {
+					prerender: None, ..
+				} => {}
+			}
+		}
+		// Render the pages that handle requests first. Export may be called from within a tokio runtime, where blocking on a runtime panics, so the requests are handled on a dedicated runtime driven from a rayon thread.
+		let request_paths = pages
+			.iter()
+			.filter(|page| matches!(page.render, Render::Request))
+			.map(|page| page.path.as_str())
+			.collect::<Vec<_>>();
+		let request_htmls = if request_paths.is_empty() {
+			HashMap::new()
+		} else {
+			rayon::scope(|_| self.render_requests(&request_paths))?
+		};
+		// Render and write the html for each page in parallel.
+		let output_html_paths = pages
+			.par_iter()
+			.map(|page| {
+				let path = &page.path;
+				let output_html_path = match (path.as_str(), url_style) {
+					("/", _) => "/index.html".to_owned(),
+					(path, _) if path.ends_with('/') => format!("{}index.html", path),
+					(path, UrlStyle::Html) => format!("{}.html", path),
+					(path, UrlStyle::TrailingSlash) => format!("{}/index.html", path),
+				};
+				let output_html_path = dist_path.join(output_html_path.trim_start_matches('/'));
+				let mut html = match &page.render {
+					Render::Handler(render) => render(path.clone())
+						.with_context(|| format!("failed to render {}", path))?,
+					Render::Request => request_htmls[path.as_str()].clone(),
+				};
+				if let (Some(base_url), Some(canonical_path)) =
+					(base_url, page.options.canonical_path.as_ref())
+				{
+					let canonical_url = format!("{}{}", base_url, canonical_path(path));
+					let link = format!(
+						r#"<link rel="canonical" href="{}">"#,
+						escape_xml(&canonical_url)
+					);
+					html = insert_into_head(&html, &link);
+				}
+				if let (Some(base_url), Some(alternates)) = (base_url, alternates.get(path)) {
+					let links = alternates
+						.iter()
+						.map(|(hreflang, alternate_path)| {
+							format!(
+								r#"<link rel="alternate" hreflang="{}" href="{}">"#,
+								escape_xml(hreflang),
+								escape_xml(&format!("{}{}", base_url, alternate_path))
+							)
+						})
+						.collect::<String>();
+					html = insert_into_head(&html, &links);
+				}
+				create_parent_dir(&output_html_path)?;
+				write_file(&output_html_path, html.as_bytes(), mode)?;
+				Ok(output_html_path)
+			})
+			.collect::<Result<Vec<_>>>()?;
+		written_paths.extend(output_html_paths);
+		// Write the not found page where static hosts look for it.
+		if let Some(not_found_handler) = &self.not_found_handler {
+			let html = not_found_handler("/404".to_owned())
+				.context("failed to render the not found page")?;
+			let not_found_path = dist_path.join("404.html");
+			write_file(&not_found_path, html.as_bytes(), mode)?;
+			written_paths.insert(not_found_path);
+		}
+		let page_paths = pages
+			.into_iter()
+			.map(|page| match url_style {
+				UrlStyle::TrailingSlash if !page.path.ends_with('/') => format!("{}/", page.path),
+				_ => page.path,
+			})
+			.collect::<Vec<_>>();
+		// Write the sitemap and robots.txt.
+		if let Some(base_url) = base_url {
+			let sitemap_format = options.sitemap_format.unwrap_or(SitemapFormat::Plain);
+			let sitemap_alternates = if options.sitemap_hreflang.unwrap_or(false) {
+				alternates
+			} else {
+				BTreeMap::new()
+			};
+			let sitemaps = sitemaps(
+				base_url,
+				&page_paths,
+				&sitemap_alternates,
+				options.sitemap_max_urls.unwrap_or(SITEMAP_MAX_URLS),
+				options.sitemap_max_bytes.unwrap_or(SITEMAP_MAX_BYTES),
+			);
+			// Write a single sitemap if everything fits in one, and otherwise write each part and an index that references them.
+			let sitemap_file_name = if let [sitemap] = sitemaps.as_slice() {
+				write_sitemap(
+					dist_path,
+					"sitemap.xml",
+					sitemap,
+					sitemap_format,
+					mode,
+					&mut written_paths,
+				)?
+			} else {
+				let mut sitemap_urls = Vec::new();
+				for (i, sitemap) in sitemaps.iter().enumerate() {
+					let file_name = format!("sitemap-{}.xml", i + 1);
+					let file_name = write_sitemap(
+						dist_path,
+						&file_name,
+						sitemap,
+						sitemap_format,
+						mode,
+						&mut written_paths,
+					)?;
+					sitemap_urls.push(format!("{}/{}", base_url, file_name));
+				}
+				write_sitemap(
+					dist_path,
+					"sitemap_index.xml",
+					&sitemap_index(&sitemap_urls),
+					sitemap_format,
+					mode,
+					&mut written_paths,
+				)?
+			};
+			let robots_txt_path = dist_path.join("robots.txt");
+			// Only append to a robots.txt copied from the output, not one left over from a previous export.
+			let copied_robots_txt_path = if written_paths.contains(&robots_txt_path) {
+				Some(robots_txt_path.as_path())
+			} else {
+				None
+			};
+			write_robots_txt(
+				&robots_txt_path,
+				copied_robots_txt_path,
+				&format!("{}/{}", base_url, sitemap_file_name),
+				mode,
+			)?;
+			written_paths.insert(robots_txt_path);
+		}
+		// Write the redirects to each requested redirect file.
+		if !self.redirects.is_empty() {
+			for redirect_file in options.redirect_files.iter().flatten() {
+				let redirect_file_path = dist_path.join(match redirect_file {
+					RedirectFile::Netlify => "_redirects",
+					RedirectFile::Vercel => "vercel.json",
+				});
+				// As with robots.txt, only extend a redirect file copied from the output.
+				let copied_contents = if written_paths.contains(&redirect_file_path) {
+					Some(
+						std::fs::read_to_string(&redirect_file_path).with_context(|| {
+							format!("failed to read {}", redirect_file_path.display())
+						})?,
+					)
+				} else {
+					None
+				};
+				let contents = match redirect_file {
+					RedirectFile::Netlify => netlify_redirects(copied_contents, &self.redirects),
+					RedirectFile::Vercel => vercel_json(copied_contents, &self.redirects)
+						.with_context(|| {
+							format!("failed to update {}", redirect_file_path.display())
+						})?,
+				};
+				write_file(&redirect_file_path, contents.as_bytes(), mode)?;
+				written_paths.insert(redirect_file_path);
+			}
+		}
+		// Write the content type of each exported file to _headers.
+		if options.headers_file.unwrap_or(false) {
+			let headers_path = dist_path.join("_headers");
+			let mut headers = if written_paths.contains(&headers_path) {
+				std::fs::read_to_string(&headers_path)
+					.with_context(|| format!("failed to read {}", headers_path.display()))?
+			} else {
+				String::new()
+			};
+			if !headers.is_empty() && !headers.ends_with('\n') {
+				headers.push('\n');
+			}
+			for path in written_paths.iter() {
+				let path = path.strip_prefix(dist_path)?;
+				let content_type = match self.content_type(path) {
+					Some(content_type) => content_type,
+					None => continue,
+				};
+				let url_path = path
+					.components()
+					.map(|component| component.as_os_str().to_string_lossy())
+					.collect::<Vec<_>>()
+					.join("/");
+				headers.push_str(&format!(
+					"/{}\n  Content-Type: {}\n",
+					url_path, content_type
+				));
+			}
+			write_file(&headers_path, headers.as_bytes(), mode)?;
+			written_paths.insert(headers_path);
+		}
+		// Report the files in dist_path that this export did not write.
+		if mode != ExportMode::Clean && options.report_orphans.unwrap_or(false) {
+			for orphan_path in orphan_paths(dist_path, &written_paths)? {
+				tracing::info!(path = %orphan_path.display(), "export did not write this file");
+			}
+		}
+		Ok(())
+	}
+}
+
+/// A page to render during export.
+struct Page<'a> {
+	path: String,
+	render: Render<'a>,
+	options: &'a RouteOptions,
+}
+
+enum Render<'a> {
+	/// Render the page with a static handler.
+	Handler(&'a (dyn Sync + Fn(String) -> Result<String>)),
+	/// Render the page by handling a `GET` request for it.
+	Request,
+}
+
+impl Sunfish {
+	/// Render the pages at `paths` by handling a request for each of them concurrently on a new runtime. This must not be called from within a tokio runtime.
+	fn render_requests<'a>(&self, paths: &[&'a str]) -> Result<HashMap<&'a str, String>> {
+		let runtime = tokio::runtime::Runtime::new()?;
+		let htmls = runtime.block_on(futures::future::join_all(
+			paths.iter().map(|path| self.render_request(path)),
+		));
+		paths
+			.iter()
+			.zip(htmls)
+			.map(|(path, html)| {
+				let html = html.with_context(|| format!("failed to render {}", path))?;
+				Ok((*path, html))
+			})
+			.collect()
+	}
+
+	/// Render the page at `path` by handling a `GET` request for it with the routes handler.
+	async fn render_request(&self, path: &str) -> Result<String> {
+		let mut request = http::Request::builder()
+			.method(http::Method::GET)
+			.uri(path)
+			.body(hyper::Body::empty())?;
+		let response = (self.routes_handler)(&mut request)
+			.await?
+			.ok_or_else(|| anyhow!("no route matched"))?;
+		if response.status() != http::StatusCode::OK {
+			return Err(anyhow!("the route responded with {}", response.status()));
+		}
+		let body = hyper::body::to_bytes(response.into_body()).await?;
+		Ok(String::from_utf8(body.to_vec())?)
+	}
+}
+
+impl ExportOptions {
+	/// Fill in the options that are not set with the ones from `other`.
+	fn merge(self, other: ExportOptions) -> ExportOptions {
+		ExportOptions {
+			mode: self.mode.or(other.mode),
+			report_orphans: self.report_orphans.or(other.report_orphans),
+			base_url: self.base_url.or(other.base_url),
+			sitemap_format: self.sitemap_format.or(other.sitemap_format),
+			hreflang_alternates: self.hreflang_alternates.or(other.hreflang_alternates),
+			sitemap_hreflang: self.sitemap_hreflang.or(other.sitemap_hreflang),
+			sitemap_max_urls: self.sitemap_max_urls.or(other.sitemap_max_urls),
+			sitemap_max_bytes: self.sitemap_max_bytes.or(other.sitemap_max_bytes),
+			redirect_files: self.redirect_files.or(other.redirect_files),
+			headers_file: self.headers_file.or(other.headers_file),
+			url_style: self.url_style.or(other.url_style),
+			ignore: self.ignore.or(other.ignore),
+		}
+	}
+}
+
+/// Read the options in `out_dir`'s export config file, or the default options if there is none.
+fn read_config(out_dir: &Path) -> Result<ExportOptions> {
+	let config_path = out_dir.join(EXPORT_CONFIG_FILE_NAME);
+	let config = match std::fs::read_to_string(&config_path) {
+		Ok(config) => config,
+		Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+			return Ok(ExportOptions::default())
+		}
+		Err(error) => return Err(error.into()),
+	};
+	let config: toml::Value = toml::from_str(&config)?;
+	let invalid = |key: &str| anyhow!("invalid value for {} in {}", key, config_path.display());
+	let keys = [
+		"mode",
+		"report_orphans",
+		"base_url",
+		"sitemap_format",
+		"redirect_files",
+		"headers_file",
+		"url_style",
+		"ignore",
+	];
+	let table = config
+		.as_table()
+		.ok_or_else(|| anyhow!("expected a table in {}", config_path.display()))?;
+	if let Some(key) = table.keys().find(|key| !keys.contains(&key.as_str())) {
+		return Err(anyhow!("unknown key {} in {}", key, config_path.display()));
+	}
+	let mode = match config.get("mode") {
+		None => None,
+		Some(mode) => match mode.as_str() {
+			Some("clean") => Some(ExportMode::Clean),
+			Some("merge") => Some(ExportMode::Merge),
+			Some("incremental") => Some(ExportMode::Incremental),
+			_ => return Err(invalid("mode")),
+		},
+	};
+	let report_orphans = match config.get("report_orphans") {
+		None => None,
+		Some(report_orphans) => Some(
+			report_orphans
+				.as_bool()
+				.ok_or_else(|| invalid("report_orphans"))?,
+		),
+	};
+	let base_url = match config.get("base_url") {
+		None => None,
+		Some(base_url) => Some(
+			base_url
+				.as_str()
+				.ok_or_else(|| invalid("base_url"))?
+				.to_owned(),
+		),
+	};
+	let sitemap_format = match config.get("sitemap_format") {
+		None => None,
+		Some(sitemap_format) => match sitemap_format.as_str() {
+			Some("plain") => Some(SitemapFormat::Plain),
+			Some("plain_and_gzip") => Some(SitemapFormat::PlainAndGzip),
+			Some("gzip") => Some(SitemapFormat::Gzip),
+			_ => return Err(invalid("sitemap_format")),
+		},
+	};
+	let redirect_files = match config.get("redirect_files") {
+		None => None,
+		Some(redirect_files) => Some(
+			redirect_files
+				.as_array()
+				.ok_or_else(|| invalid("redirect_files"))?
+				.iter()
+				.map(|redirect_file| match redirect_file.as_str() {
+					Some("netlify") => Ok(RedirectFile::Netlify),
+					Some("vercel") => Ok(RedirectFile::Vercel),
+					_ => Err(invalid("redirect_files")),
+				})
+				.collect::<Result<Vec<_>>>()?,
+		),
+	};
+	let headers_file = match config.get("headers_file") {
+		None => None,
+		Some(headers_file) => Some(
+			headers_file
+				.as_bool()
+				.ok_or_else(|| invalid("headers_file"))?,
+		),
+	};
+	let url_style = match config.get("url_style") {
+		None => None,
+		Some(url_style) => match url_style.as_str() {
+			Some("html") => Some(UrlStyle::Html),
+			Some("trailing_slash") => Some(UrlStyle::TrailingSlash),
+			_ => return Err(invalid("url_style")),
+		},
+	};
+	let ignore = match config.get("ignore") {
+		None => None,
+		Some(ignore) => Some(
+			ignore
+				.as_array()
+				.ok_or_else(|| invalid("ignore"))?
+				.iter()
+				.map(|pattern| {
+					pattern
+						.as_str()
+						.map(str::to_owned)
+						.ok_or_else(|| invalid("ignore"))
+				})
+				.collect::<Result<Vec<_>>>()?,
+		),
+	};
+	Ok(ExportOptions {
+		mode,
+		report_orphans,
+		base_url,
+		sitemap_format,
+		redirect_files,
+		headers_file,
+		url_style,
+		ignore,
+		..Default::default()
+	})
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+	let parent = match path.parent() {
+		Some(parent) => parent,
+		None => return Ok(()),
+	};
+	std::fs::create_dir_all(parent)
+		.with_context(|| format!("failed to create {}", parent.display()))
+}
+
+/// Write `contents` to `path`. In `ExportMode::Incremental`, a file that already has these contents is left untouched.
+fn write_file(path: &Path, contents: &[u8], mode: ExportMode) -> Result<()> {
+	if mode == ExportMode::Incremental && std::fs::read(path).ok().as_deref() == Some(contents) {
+		return Ok(());
+	}
+	std::fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn orphan_paths(dist_path: &Path, written_paths: &BTreeSet<PathBuf>) -> Result<Vec<PathBuf>> {
+	let mut orphan_paths = Vec::new();
+	for entry in walkdir::WalkDir::new(dist_path).sort_by_file_name() {
+		let entry = entry?;
+		if entry.file_type().is_file() && !written_paths.contains(entry.path()) {
+			orphan_paths.push(entry.path().to_owned());
+		}
+	}
+	Ok(orphan_paths)
+}
+
+/// Map the path of each page with `hreflang` alternates to the alternates in its group.
+fn alternates_by_path(
+	hreflang_alternates: Option<&BTreeMap<String, BTreeMap<String, String>>>,
+) -> BTreeMap<String, BTreeMap<String, String>> {
+	let mut alternates_by_path = BTreeMap::new();
+	for alternates in hreflang_alternates
+		.into_iter()
+		.flat_map(|groups| groups.values())
+	{
+		for path in alternates.values() {
+			alternates_by_path.insert(path.clone(), alternates.clone());
+		}
+	}
+	alternates_by_path
+}
+
+/// Build the sitemaps listing `page_paths`, splitting them so that no sitemap has more than `max_urls` URLs or `max_bytes` bytes before compression.
+fn sitemaps(
+	base_url: &str,
+	page_paths: &[String],
+	alternates: &BTreeMap<String, BTreeMap<String, String>>,
+	max_urls: usize,
+	max_bytes: usize,
+) -> Vec<String> {
+	let mut header = String::new();
+	header.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+	header.push('\n');
+	if alternates.is_empty() {
+		header.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+	} else {
+		header.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:xhtml="http://www.w3.org/1999/xhtml">"#);
+	}
+	header.push('\n');
+	let footer = "</urlset>\n";
+	let mut sitemaps = Vec::new();
+	let mut sitemap = header.clone();
+	let mut url_count = 0;
+	for path in page_paths {
+		let entry = sitemap_entry(base_url, path, alternates.get(path));
+		let is_full =
+			url_count >= max_urls || sitemap.len() + entry.len() + footer.len() > max_bytes;
+		if url_count > 0 && is_full {
+			sitemap.push_str(footer);
+			sitemaps.push(std::mem::replace(&mut sitemap, header.clone()));
+			url_count = 0;
+		}
+		sitemap.push_str(&entry);
+		url_count += 1;
+	}
+	sitemap.push_str(footer);
+	sitemaps.push(sitemap);
+	sitemaps
+}
+
+fn sitemap_entry(
+	base_url: &str,
+	path: &str,
+	alternates: Option<&BTreeMap<String, String>>,
+) -> String {
+	let url = format!("{}{}", base_url, path);
+	let alternates = match alternates {
+		Some(alternates) => alternates,
+		None => return format!("\t<url><loc>{}</loc></url>\n", escape_xml(&url)),
+	};
+	let mut entry = format!("\t<url>\n\t\t<loc>{}</loc>\n", escape_xml(&url));
+	for (hreflang, alternate_path) in alternates {
+		let alternate_url = format!("{}{}", base_url, alternate_path);
+		entry.push_str(&format!(
+			"\t\t<xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>\n",
+			escape_xml(hreflang),
+			escape_xml(&alternate_url)
+		));
+	}
+	entry.push_str("\t</url>\n");
+	entry
+}
+
+fn sitemap_index(sitemap_urls: &[String]) -> String {
+	let mut sitemap_index = String::new();
+	sitemap_index.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+	sitemap_index.push('\n');
+	sitemap_index.push_str(r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+	sitemap_index.push('\n');
+	for sitemap_url in sitemap_urls {
+		sitemap_index.push_str(&format!(
+			"\t<sitemap><loc>{}</loc></sitemap>\n",
+			escape_xml(sitemap_url)
+		));
+	}
+	sitemap_index.push_str("</sitemapindex>\n");
+	sitemap_index
+}
+
+/// Write `contents` to `file_name` in `dist_path` in `sitemap_format`, returning the name of the file that should be referenced, which is the gzipped one only if the plain one was not written.
+fn write_sitemap(
+	dist_path: &Path,
+	file_name: &str,
+	contents: &str,
+	sitemap_format: SitemapFormat,
+	mode: ExportMode,
+	written_paths: &mut BTreeSet<PathBuf>,
+) -> Result<String> {
+	let gzip_file_name = format!("{}.gz", file_name);
+	if sitemap_format != SitemapFormat::Gzip {
+		let sitemap_path = dist_path.join(file_name);
+		write_file(&sitemap_path, contents.as_bytes(), mode)?;
+		written_paths.insert(sitemap_path);
+	}
+	if sitemap_format != SitemapFormat::Plain {
+		let sitemap_path = dist_path.join(&gzip_file_name);
+		write_file(&sitemap_path, &gzip(contents.as_bytes())?, mode)?;
+		written_paths.insert(sitemap_path);
+	}
+	match sitemap_format {
+		SitemapFormat::Plain | SitemapFormat::PlainAndGzip => Ok(file_name.to_owned()),
+		SitemapFormat::Gzip => Ok(gzip_file_name),
+	}
+}
+
+/// Write a robots.txt that points to the sitemap, appending to the robots.txt copied from the output if there is one.
+fn write_robots_txt(
+	robots_txt_path: &Path,
+	copied_robots_txt_path: Option<&Path>,
+	sitemap_url: &str,
+	mode: ExportMode,
+) -> Result<()> {
+	let mut robots_txt = match copied_robots_txt_path {
+		Some(copied_robots_txt_path) => std::fs::read_to_string(copied_robots_txt_path)?,
+		None => "User-agent: *\nAllow: /\n".to_owned(),
+	};
+	if !robots_txt.is_empty() && !robots_txt.ends_with('\n') {
+		robots_txt.push('\n');
+	}
+	robots_txt.push_str(&format!("Sitemap: {}\n", sitemap_url));
+	write_file(robots_txt_path, robots_txt.as_bytes(), mode)
+}
+
+/// Append a line for each redirect to the contents of a `_redirects` file.
+fn netlify_redirects(copied_contents: Option<String>, redirects: &[Redirect]) -> String {
+	let mut contents = copied_contents.unwrap_or_default();
+	if !contents.is_empty() && !contents.ends_with('\n') {
+		contents.push('\n');
+	}
+	for redirect in redirects {
+		let status = if redirect.permanent { 301 } else { 302 };
+		contents.push_str(&format!("{} {} {}\n", redirect.from, redirect.to, status));
+	}
+	contents
+}
+
+/// Append the redirects to the `redirects` array of a `vercel.json` file, keeping any other configuration it has.
+fn vercel_json(copied_contents: Option<String>, redirects: &[Redirect]) -> Result<String> {
+	let mut config = match copied_contents {
+		Some(copied_contents) => serde_json::from_str(&copied_contents)?,
+		None => serde_json::json!({}),
+	};
+	let config_redirects = config
+		.as_object_mut()
+		.ok_or_else(|| anyhow!("expected an object"))?
+		.entry("redirects")
+		.or_insert_with(|| serde_json::json!([]))
+		.as_array_mut()
+		.ok_or_else(|| anyhow!("expected redirects to be an array"))?;
+	for redirect in redirects {
+		// Use statusCode rather than permanent, which would send 308 and 307.
+		let status_code = if redirect.permanent { 301 } else { 302 };
+		config_redirects.push(serde_json::json!({
+			"source": redirect.from,
+			"destination": redirect.to,
+			"statusCode": status_code,
+		}));
+	}
+	let mut contents = serde_json::to_string_pretty(&config)?;
+	contents.push('\n');
+	Ok(contents)
+}
+
+/// Insert `element` at the end of the page's `<head>`, or at the start of the page if it has none.
+fn insert_into_head(html: &str, element: &str) -> String {
+	match html.to_ascii_lowercase().find("</head>") {
+		Some(index) => format!("{}{}{}", &html[..index], element, &html[index..]),
+		None => format!("{}{}", element, html),
+	}
+}
+
+fn gzip(bytes: &[u8]) -> Result<Vec<u8>> {
+	let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+	encoder.write_all(bytes)?;
+	Ok(encoder.finish()?)
+}
+
+fn escape_xml(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Get the path of the entry a walk failed to read, if the error has one.
+fn walk_error_path(error: &ignore::Error) -> Option<&Path> {
+	match error {
+		ignore::Error::WithPath { path, .. } => Some(path),
+		ignore::Error::Loop { child, .. } => Some(child),
+		ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+			walk_error_path(err)
+		}
+		ignore::Error::Partial(errors) => errors.iter().find_map(walk_error_path),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		include_dir::{FsDirectory, IncludeDir, PathFilter},
+		RouteInitializer,
+	};
+
+	fn post_route() -> Route {
+		Route::new_dynamic_with_prerender(
+			|| vec!["/posts/1".to_owned(), "/posts/2".to_owned()],
+			|_| unreachable!("export handles requests with the routes handler"),
+		)
+	}
+
+	fn sunfish(out_dir: &Path) -> Sunfish {
+		let output_path = out_dir.join("output");
+		std::fs::create_dir_all(&output_path).unwrap();
+		Sunfish::new(
+			IncludeDir::Fs(FsDirectory(output_path, PathFilter::default())),
+			Box::new(|request| {
+				let body = format!("<p>{}</p>", request.uri().path());
+				Box::pin(async move { Ok(Some(http::Response::new(hyper::Body::from(body)))) })
+			}),
+			vec![RouteInitializer {
+				path_with_placeholders: "/posts/_id".to_owned(),
+				init: post_route,
+				client_crate_name: None,
+			}],
+		)
+	}
+
+	fn assert_exported(dist_path: &Path) {
+		for id in ["1", "2"] {
+			let html =
+				std::fs::read_to_string(dist_path.join(format!("posts/{}.html", id))).unwrap();
+			assert_eq!(html, format!("<p>/posts/{}</p>", id));
+		}
+	}
+
+	#[test]
+	fn export_prerenders_dynamic_routes() {
+		let out_dir = tempfile::tempdir().unwrap();
+		let dist_dir = tempfile::tempdir().unwrap();
+		sunfish(out_dir.path())
+			.export(out_dir.path(), dist_dir.path())
+			.unwrap();
+		assert_exported(dist_dir.path());
+	}
+
+	#[tokio::test(flavor = "current_thread")]
+	async fn export_prerenders_dynamic_routes_within_a_runtime() {
+		let out_dir = tempfile::tempdir().unwrap();
+		let dist_dir = tempfile::tempdir().unwrap();
+		sunfish(out_dir.path())
+			.export(out_dir.path(), dist_dir.path())
+			.unwrap();
+		assert_exported(dist_dir.path());
+	}
+
+	#[test]
+	fn export_reads_url_style_and_ignore_from_the_config_file() {
+		let out_dir = tempfile::tempdir().unwrap();
+		let dist_dir = tempfile::tempdir().unwrap();
+		let sunfish = sunfish(out_dir.path());
+		std::fs::write(out_dir.path().join("output/app.css"), "").unwrap();
+		std::fs::write(out_dir.path().join("output/app.css.map"), "").unwrap();
+		std::fs::write(
+			out_dir.path().join(EXPORT_CONFIG_FILE_NAME),
+			"url_style = \"trailing_slash\"\nignore = [\"*.map\"]\n",
+		)
+		.unwrap();
+		sunfish.export(out_dir.path(), dist_dir.path()).unwrap();
+		assert!(dist_dir.path().join("app.css").exists());
+		assert!(!dist_dir.path().join("app.css.map").exists());
+		assert!(dist_dir.path().join("posts/1/index.html").exists());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn export_reports_broken_entries_by_path() {
+		let out_dir = tempfile::tempdir().unwrap();
+		let dist_dir = tempfile::tempdir().unwrap();
+		let sunfish = sunfish(out_dir.path());
+		std::fs::write(out_dir.path().join("output/app.css"), "").unwrap();
+		std::os::unix::fs::symlink(
+			out_dir.path().join("missing"),
+			out_dir.path().join("output/broken.css"),
+		)
+		.unwrap();
+		let error = sunfish.export(out_dir.path(), dist_dir.path()).unwrap_err();
+		let message = format!("{:#}", error);
+		assert!(message.contains("broken.css"), "{}", message);
+	}
+
+	#[test]
+	fn walk_errors_are_reported_with_their_path() {
+		let error = ignore::Error::WithDepth {
+			depth: 1,
+			err: Box::new(ignore::Error::WithPath {
+				path: PathBuf::from("output/unreadable"),
+				err: Box::new(ignore::Error::Io(std::io::Error::from(
+					std::io::ErrorKind::PermissionDenied,
+				))),
+			}),
+		};
+		assert_eq!(
+			walk_error_path(&error),
+			Some(Path::new("output/unreadable"))
+		);
+	}
+
+	#[test]
+	fn export_rejects_unknown_config_keys() {
+		let out_dir = tempfile::tempdir().unwrap();
+		std::fs::write(
+			out_dir.path().join(EXPORT_CONFIG_FILE_NAME),
+			"base_ulr = \"https://example.com\"\n",
+		)
+		.unwrap();
+		assert!(read_config(out_dir.path()).is_err());
+	}
+}
@@ -1,9 +1,32 @@
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use hyper::body::Bytes;
+use ignore::{
+	gitignore::{Gitignore, GitignoreBuilder},
+	overrides::{Override, OverrideBuilder},
+};
+pub use once_cell::sync::OnceCell;
 use std::{
 	borrow::Cow,
-	collections::BTreeMap,
+	collections::{BTreeMap, HashMap},
+	io::Read,
 	path::{Path, PathBuf},
+	time::{Duration, SystemTime},
 };
 
+/// Panic if `algorithm`, the hash algorithm the `include_dir!` macro used, differs from the one sunfish was compiled with. The macro calls this when the directory is created.
+pub fn check_hash_algorithm(algorithm: &str) {
+	if algorithm != crate::HASH_ALGORITHM {
+		panic!(
+			"include_dir! hashed files with \"{}\" but sunfish was compiled to use \"{}\". Set SUNFISH_HASH_ALGORITHM to the same value when compiling both.",
+			algorithm,
+			crate::HASH_ALGORITHM,
+		);
+	}
+}
+
+/// The size of the chunks `FsOrIncludedFile::stream` yields.
+const CHUNK_SIZE: usize = 64 * 1024;
+
 pub enum IncludeDir {
 	Fs(FsDirectory),
 	Included(IncludedDirectory),
@@ -15,12 +38,73 @@ pub enum FsOrIncludedFile {
 }
 
 impl IncludeDir {
-	pub fn read(&self, path: &Path) -> Option<FsOrIncludedFile> {
+	/// Read the file at `path`, returning `None` if there is no file there and an error if the file exists but cannot be read.
+	pub fn read(&self, path: &Path) -> std::io::Result<Option<FsOrIncludedFile>> {
 		match self {
 			IncludeDir::Fs(s) => s.read(path),
 			IncludeDir::Included(s) => s.read(path),
 		}
 	}
+
+	/// Find the path of the file whose contents have the full hash `full_hash`. For the `Fs` variant, this hashes every file in the directory.
+	pub fn find_by_full_hash(&self, full_hash: &str) -> Option<PathBuf> {
+		self.paths().into_iter().find(|path| {
+			let file = match self.read(path) {
+				Ok(Some(file)) => file,
+				Ok(None) | Err(_) => return false,
+			};
+			match file.full_hash() {
+				Some(file_full_hash) => file_full_hash == full_hash,
+				None => file
+					.data()
+					.map_or(false, |data| crate::full_hash(data) == full_hash),
+			}
+		})
+	}
+
+	/// Get the paths of all files in the directory, relative to its root, in sorted order.
+	pub fn paths(&self) -> Vec<PathBuf> {
+		match self {
+			IncludeDir::Fs(s) => s.paths(),
+			IncludeDir::Included(s) => s.paths(),
+		}
+	}
+
+	/// Get the total size in bytes of all files in the directory. For included directories this is the number of bytes embedded in the binary, which counts gzipped copies and, for files embedded only gzipped, just the gzipped contents.
+	pub fn total_size(&self) -> usize {
+		self.sizes().iter().map(|(_, size)| size).sum()
+	}
+
+	/// Get the total size in bytes of the files with each extension, counted as in `total_size`. Files without an extension are counted under the empty string.
+	pub fn size_by_extension(&self) -> BTreeMap<String, usize> {
+		let mut size_by_extension = BTreeMap::new();
+		for (path, size) in self.sizes() {
+			let extension = path
+				.extension()
+				.map(|extension| extension.to_string_lossy().to_lowercase())
+				.unwrap_or_default();
+			*size_by_extension.entry(extension).or_insert(0) += size;
+		}
+		size_by_extension
+	}
+
+	fn sizes(&self) -> Vec<(PathBuf, usize)> {
+		match self {
+			IncludeDir::Fs(s) => s
+				.paths()
+				.into_iter()
+				.filter_map(|path| {
+					let metadata = std::fs::metadata(s.0.join(&path)).ok()?;
+					Some((path, metadata.len() as usize))
+				})
+				.collect(),
+			IncludeDir::Included(s) => {
+				s.0.iter()
+					.map(|(path, file)| (path.to_path_buf(), file.embedded_size()))
+					.collect()
+			}
+		}
+	}
 }
 
 impl IntoIterator for IncludeDir {
@@ -29,7 +113,9 @@ impl IntoIterator for IncludeDir {
 	fn into_iter(self) -> Self::IntoIter {
 		match self {
 			IncludeDir::Fs(fs) => FsOrIncludedIntoIter::Fs(
-				walkdir::WalkDir::new(fs.0).sort_by_file_name().into_iter(),
+				walkdir::WalkDir::new(&fs.0).sort_by_file_name().into_iter(),
+				Box::new(fs),
+				HashMap::new(),
 			),
 			IncludeDir::Included(embedded) => {
 				FsOrIncludedIntoIter::Included(embedded.0.into_iter())
@@ -39,7 +125,7 @@ impl IntoIterator for IncludeDir {
 }
 
 pub enum FsOrIncludedIntoIter {
-	Fs(walkdir::IntoIter),
+	Fs(walkdir::IntoIter, Box<FsDirectory>, IgnoreFiles),
 	Included(std::collections::btree_map::IntoIter<&'static Path, IncludedFile>),
 }
 
@@ -47,13 +133,17 @@ impl Iterator for FsOrIncludedIntoIter {
 	type Item = (PathBuf, FsOrIncludedFile);
 	fn next(&mut self) -> Option<Self::Item> {
 		match self {
-			FsOrIncludedIntoIter::Fs(walkdir) => loop {
+			FsOrIncludedIntoIter::Fs(walkdir, fs, ignore_files) => loop {
 				let entry = match walkdir.next() {
 					None => return None,
 					Some(Err(e)) => panic!("{}", e),
 					Some(Ok(entry)) => entry,
 				};
-				if entry.file_type().is_file() {
+				let is_included = entry
+					.path()
+					.strip_prefix(&fs.0)
+					.map_or(false, |path| fs.is_included_with(path, ignore_files));
+				if entry.file_type().is_file() && is_included {
 					let path = entry.path().to_owned();
 					return Some((path.clone(), FsOrIncludedFile::Fs(FsFile(path))));
 				} else {
@@ -68,10 +158,11 @@ impl Iterator for FsOrIncludedIntoIter {
 }
 
 impl FsOrIncludedFile {
-	pub fn data(&self) -> Cow<'static, [u8]> {
+	/// Read the file's contents. Only files read from the filesystem can fail to be read, for example if they were deleted after the file was opened.
+	pub fn data(&self) -> std::io::Result<Cow<'static, [u8]>> {
 		match self {
-			FsOrIncludedFile::Fs(s) => s.data(),
-			FsOrIncludedFile::Included(s) => s.data(),
+			FsOrIncludedFile::Fs(s) => s.data().map(Cow::Owned),
+			FsOrIncludedFile::Included(s) => Ok(s.data()),
 		}
 	}
 
@@ -81,31 +172,272 @@ impl FsOrIncludedFile {
 			FsOrIncludedFile::Included(s) => s.hash(),
 		}
 	}
+
+	pub fn full_hash(&self) -> Option<&'static str> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.full_hash(),
+			FsOrIncludedFile::Included(s) => s.full_hash(),
+		}
+	}
+
+	pub fn gzip(&self) -> Option<&'static [u8]> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.gzip(),
+			FsOrIncludedFile::Included(s) => s.gzip(),
+		}
+	}
+
+	/// Get the time the file was last modified: its modification time on disk for files read from the filesystem, and the build time for included files.
+	pub fn modified(&self) -> Option<SystemTime> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.modified(),
+			FsOrIncludedFile::Included(s) => Some(s.modified()),
+		}
+	}
+
+	/// Read at most the first `len` bytes of the file's contents, without reading the rest of the file into memory.
+	pub fn prefix(&self, len: usize) -> std::io::Result<Cow<'static, [u8]>> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.prefix(len).map(Cow::Owned),
+			FsOrIncludedFile::Included(s) => Ok(s.prefix(len)),
+		}
+	}
+
+	/// Get the size in bytes of the file's contents without reading them.
+	pub fn size(&self) -> std::io::Result<u64> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.size(),
+			FsOrIncludedFile::Included(s) => Ok(s.size()),
+		}
+	}
+
+	/// Stream the file's contents in chunks instead of reading them into memory at once.
+	pub fn stream(&self) -> BoxStream<'static, std::io::Result<Bytes>> {
+		match self {
+			FsOrIncludedFile::Fs(s) => s.stream(),
+			FsOrIncludedFile::Included(s) => s.stream(),
+		}
+	}
 }
 
-pub struct FsDirectory(pub PathBuf);
+/// A directory read from the filesystem, containing only the files its filter includes and its `.gitignore` and `.ignore` files do not leave out.
+pub struct FsDirectory(pub PathBuf, pub PathFilter);
+
+/// The parsed `.ignore` and `.gitignore` files in each directory, so each is read only once while walking.
+type IgnoreFiles = HashMap<PathBuf, Vec<Gitignore>>;
 
 impl FsDirectory {
-	pub fn read(&self, path: &Path) -> Option<FsOrIncludedFile> {
+	pub fn read(&self, path: &Path) -> std::io::Result<Option<FsOrIncludedFile>> {
+		if !self.is_included(path) {
+			return Ok(None);
+		}
 		let path = self.0.join(path);
-		if path.exists() {
-			Some(FsOrIncludedFile::Fs(FsFile(path)))
-		} else {
-			None
+		let metadata = match std::fs::metadata(&path) {
+			Ok(metadata) => metadata,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+			// A path that descends into a file is also a miss, not a read failure.
+			Err(_) if !path.parent().map_or(false, Path::is_dir) => return Ok(None),
+			Err(error) => return Err(error),
+		};
+		if !metadata.is_file() {
+			return Ok(None);
 		}
+		// Open the file to surface errors such as missing permissions now instead of when its data is read.
+		std::fs::File::open(&path)?;
+		Ok(Some(FsOrIncludedFile::Fs(FsFile(path))))
 	}
 }
 
+impl FsDirectory {
+	/// Whether the directory's root exists. It may not if the project was moved after it was built, since the root's path is fixed at compile time.
+	pub fn root_exists(&self) -> bool {
+		self.0.is_dir()
+	}
+
+	/// Find the path of the file whose contents have the full hash `full_hash` by hashing every file in the directory.
+	pub fn find_by_full_hash(&self, full_hash: &str) -> Option<PathBuf> {
+		self.paths().into_iter().find(|path| {
+			std::fs::read(self.0.join(path))
+				.map_or(false, |data| crate::full_hash(data) == full_hash)
+		})
+	}
+
+	pub fn paths(&self) -> Vec<PathBuf> {
+		let mut ignore_files = HashMap::new();
+		walkdir::WalkDir::new(&self.0)
+			.sort_by_file_name()
+			.into_iter()
+			.filter_map(Result::ok)
+			.filter(|entry| entry.file_type().is_file())
+			.filter_map(|entry| entry.path().strip_prefix(&self.0).ok().map(Path::to_owned))
+			.filter(|path| self.is_included_with(path, &mut ignore_files))
+			.collect()
+	}
+
+	/// Whether the file at `path`, relative to the root, is in the directory. This applies the same rules as `include_dir!` does when embedding the directory.
+	pub fn is_included(&self, path: &Path) -> bool {
+		self.is_included_with(path, &mut HashMap::new())
+	}
+
+	fn is_included_with(&self, path: &Path, ignore_files: &mut IgnoreFiles) -> bool {
+		self.1.is_included(path) && !self.is_ignored(path, ignore_files)
+	}
+
+	/// Whether `.ignore` or `.gitignore` files leave out the file at `path` or one of the directories it is in. As when walking a directory with the `ignore` crate, files in deeper directories take precedence, `.ignore` takes precedence over `.gitignore` in the same directory, and a path the filter's globs include is never ignored.
+	fn is_ignored(&self, path: &Path, ignore_files: &mut IgnoreFiles) -> bool {
+		let mut entries = path
+			.ancestors()
+			.filter(|entry| !entry.as_os_str().is_empty())
+			.collect::<Vec<_>>();
+		entries.reverse();
+		let file_index = entries.len().saturating_sub(1);
+		entries.into_iter().enumerate().any(|(index, entry)| {
+			let is_dir = index != file_index;
+			if let Some(overrides) = &self.1 .0 {
+				if overrides.matched(entry, is_dir).is_whitelist() {
+					return false;
+				}
+			}
+			let absolute_path = self.0.join(entry);
+			entry
+				.ancestors()
+				.skip(1)
+				.find_map(|dir| {
+					let dir = self.0.join(dir);
+					ignore_files
+						.entry(dir.clone())
+						.or_insert_with(|| read_ignore_files(&dir))
+						.iter()
+						.map(|ignore_file| ignore_file.matched(&absolute_path, is_dir))
+						.find(|matched| !matched.is_none())
+						.map(|matched| matched.is_ignore())
+				})
+				.unwrap_or(false)
+		})
+	}
+}
+
+/// Parse the `.ignore` and `.gitignore` files in `dir`, in order of precedence. Files that fail to parse are skipped.
+fn read_ignore_files(dir: &Path) -> Vec<Gitignore> {
+	[".ignore", ".gitignore"]
+		.iter()
+		.map(|file_name| dir.join(file_name))
+		.filter(|path| path.is_file())
+		.filter_map(|path| {
+			let mut builder = GitignoreBuilder::new(dir);
+			builder.add(path);
+			builder.build().ok()
+		})
+		.collect()
+}
+
+/// Glob patterns that choose which files in an `FsDirectory` are included. Hidden files and directories, whose names start with a dot, are never included, except for `.well-known`. `include_dir!` applies the same rules when embedding a directory, so debug and release builds serve the same files.
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter(Option<Override>);
+
+impl PathFilter {
+	/// Patterns use gitignore syntax and match paths relative to `root`. If `include` is not empty, only files matching one of its patterns are included. Files matching any `exclude` pattern, or in a directory that does, are left out.
+	pub fn new(
+		root: &Path,
+		include: &[&str],
+		exclude: &[&str],
+	) -> Result<PathFilter, ignore::Error> {
+		if include.is_empty() && exclude.is_empty() {
+			return Ok(PathFilter(None));
+		}
+		// Exclude patterns are added negated, which makes them ignore the files they match.
+		let mut overrides = OverrideBuilder::new(root);
+		for pattern in include {
+			overrides.add(pattern)?;
+		}
+		for pattern in exclude {
+			overrides.add(&format!("!{}", pattern))?;
+		}
+		Ok(PathFilter(Some(overrides.build()?)))
+	}
+
+	/// Whether the file at `path`, relative to the root, is included.
+	pub fn is_included(&self, path: &Path) -> bool {
+		if path
+			.components()
+			.any(|component| is_hidden(component.as_os_str()))
+		{
+			return false;
+		}
+		let overrides = match &self.0 {
+			Some(overrides) => overrides,
+			None => return true,
+		};
+		if overrides.matched(path, false).is_ignore() {
+			return false;
+		}
+		// Walking a directory skips excluded directories, so leave out the files in them too.
+		path.ancestors()
+			.skip(1)
+			.filter(|ancestor| !ancestor.as_os_str().is_empty())
+			.all(|ancestor| !overrides.matched(ancestor, true).is_ignore())
+	}
+}
+
+fn is_hidden(file_name: &std::ffi::OsStr) -> bool {
+	let file_name = file_name.to_string_lossy();
+	file_name.starts_with('.') && file_name != ".well-known"
+}
+
 pub struct FsFile(pub PathBuf);
 
 impl FsFile {
-	pub fn data(&self) -> Cow<'static, [u8]> {
-		Cow::Owned(std::fs::read(&self.0).unwrap())
+	pub fn data(&self) -> std::io::Result<Vec<u8>> {
+		std::fs::read(&self.0)
 	}
 
 	pub fn hash(&self) -> Option<&'static str> {
 		None
 	}
+
+	pub fn full_hash(&self) -> Option<&'static str> {
+		None
+	}
+
+	pub fn gzip(&self) -> Option<&'static [u8]> {
+		None
+	}
+
+	pub fn prefix(&self, len: usize) -> std::io::Result<Vec<u8>> {
+		let mut prefix = Vec::new();
+		std::fs::File::open(&self.0)?
+			.take(len as u64)
+			.read_to_end(&mut prefix)?;
+		Ok(prefix)
+	}
+
+	pub fn size(&self) -> std::io::Result<u64> {
+		Ok(std::fs::metadata(&self.0)?.len())
+	}
+
+	pub fn modified(&self) -> Option<SystemTime> {
+		std::fs::metadata(&self.0).ok()?.modified().ok()
+	}
+
+	/// Stream the file from disk, so that changes to it are seen on the next request.
+	pub fn stream(&self) -> BoxStream<'static, std::io::Result<Bytes>> {
+		let path = self.0.clone();
+		futures::stream::once(tokio::fs::File::open(path))
+			.map_ok(|file| {
+				futures::stream::try_unfold(file, |mut file| async move {
+					use tokio::io::AsyncReadExt;
+					let mut buffer = vec![0; CHUNK_SIZE];
+					let n = file.read(&mut buffer).await?;
+					if n == 0 {
+						return Ok(None);
+					}
+					buffer.truncate(n);
+					Ok(Some((Bytes::from(buffer), file)))
+				})
+			})
+			.try_flatten()
+			.boxed()
+	}
 }
 
 #[derive(Debug)]
@@ -113,24 +445,234 @@ pub struct IncludedDirectory(pub BTreeMap<&'static Path, IncludedFile>);
 
 #[derive(Clone, Debug)]
 pub struct IncludedFile {
-	pub data: &'static [u8],
+	pub data: IncludedData,
+	/// The truncated hash of the file's contents.
 	pub hash: &'static str,
+	/// The full hex-encoded hash of the file's contents.
+	pub full_hash: &'static str,
+	/// The file's contents compressed with gzip, if `include_dir!` was asked to compress and compressing made the file smaller. Gzip is the only compressed variant that is embedded.
+	pub gzip: Option<&'static [u8]>,
+	/// When the file was included, in seconds since the Unix epoch.
+	pub modified: u64,
+}
+
+/// The contents of an included file as they were embedded.
+#[derive(Clone, Copy, Debug)]
+pub enum IncludedData {
+	Raw(&'static [u8]),
+	/// Only the gzipped contents were embedded. They are decompressed into `decompressed` the first time they are read.
+	Gzip {
+		gzip: &'static [u8],
+		size: usize,
+		decompressed: &'static OnceCell<Vec<u8>>,
+	},
 }
 
 impl IncludedDirectory {
-	pub fn read(&self, path: &Path) -> Option<FsOrIncludedFile> {
-		self.0
+	pub fn read(&self, path: &Path) -> std::io::Result<Option<FsOrIncludedFile>> {
+		Ok(self
+			.0
 			.get(path)
-			.map(|file| FsOrIncludedFile::Included(file.clone()))
+			.map(|file| FsOrIncludedFile::Included(file.clone())))
+	}
+}
+
+impl IncludedDirectory {
+	pub fn paths(&self) -> Vec<PathBuf> {
+		self.0.keys().map(|path| path.to_path_buf()).collect()
 	}
 }
 
 impl IncludedFile {
 	pub fn data(&self) -> Cow<'static, [u8]> {
-		Cow::Borrowed(self.data)
+		Cow::Borrowed(self.static_data())
+	}
+
+	fn static_data(&self) -> &'static [u8] {
+		match self.data {
+			IncludedData::Raw(data) => data,
+			IncludedData::Gzip {
+				gzip, decompressed, ..
+			} => decompressed.get_or_init(|| {
+				let mut data = Vec::new();
+				flate2::read::GzDecoder::new(gzip)
+					.read_to_end(&mut data)
+					.expect("failed to decompress an included file");
+				data
+			}),
+		}
+	}
+
+	/// Get at most the first `len` bytes of the contents. Files embedded only gzipped that have not been read yet are decompressed only as far as needed.
+	pub fn prefix(&self, len: usize) -> Cow<'static, [u8]> {
+		let data = match self.data {
+			IncludedData::Raw(data) => data,
+			IncludedData::Gzip {
+				gzip, decompressed, ..
+			} => match decompressed.get() {
+				Some(data) => data,
+				None => {
+					let mut prefix = Vec::new();
+					flate2::read::GzDecoder::new(gzip)
+						.take(len as u64)
+						.read_to_end(&mut prefix)
+						.expect("failed to decompress an included file");
+					return Cow::Owned(prefix);
+				}
+			},
+		};
+		Cow::Borrowed(&data[..data.len().min(len)])
 	}
 
 	pub fn hash(&self) -> Option<&'static str> {
 		Some(self.hash)
 	}
+
+	pub fn full_hash(&self) -> Option<&'static str> {
+		Some(self.full_hash)
+	}
+
+	pub fn gzip(&self) -> Option<&'static [u8]> {
+		self.gzip
+	}
+
+	pub fn modified(&self) -> SystemTime {
+		SystemTime::UNIX_EPOCH + Duration::from_secs(self.modified)
+	}
+
+	/// Get the number of bytes embedded in the binary for the file.
+	pub fn embedded_size(&self) -> usize {
+		match self.data {
+			IncludedData::Raw(data) => data.len() + self.gzip.map_or(0, <[u8]>::len),
+			IncludedData::Gzip { gzip, .. } => gzip.len(),
+		}
+	}
+
+	pub fn size(&self) -> u64 {
+		match self.data {
+			IncludedData::Raw(data) => data.len() as u64,
+			IncludedData::Gzip { size, .. } => size as u64,
+		}
+	}
+
+	/// Stream the file's contents in chunks that borrow the embedded data without copying it.
+	pub fn stream(&self) -> BoxStream<'static, std::io::Result<Bytes>> {
+		let chunks = self
+			.static_data()
+			.chunks(CHUNK_SIZE)
+			.map(|chunk| Ok(Bytes::from_static(chunk)));
+		futures::stream::iter(chunks).boxed()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fs_directories_leave_out_hidden_and_ignored_files() {
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::create_dir_all(dir.path().join(".well-known")).unwrap();
+		std::fs::create_dir_all(dir.path().join(".cache")).unwrap();
+		std::fs::write(dir.path().join(".gitignore"), "ignored.txt\n").unwrap();
+		std::fs::write(dir.path().join("ignored.txt"), "").unwrap();
+		std::fs::write(dir.path().join("index.html"), "").unwrap();
+		std::fs::write(dir.path().join(".cache/file"), "").unwrap();
+		std::fs::write(dir.path().join(".well-known/security.txt"), "").unwrap();
+		let fs_directory = FsDirectory(dir.path().to_owned(), PathFilter::default());
+		assert_eq!(
+			fs_directory.paths(),
+			vec![
+				PathBuf::from(".well-known/security.txt"),
+				PathBuf::from("index.html"),
+			]
+		);
+		assert!(fs_directory
+			.read(Path::new("ignored.txt"))
+			.unwrap()
+			.is_none());
+		assert!(fs_directory
+			.read(Path::new(".gitignore"))
+			.unwrap()
+			.is_none());
+	}
+
+	#[test]
+	fn fs_directories_apply_nested_ignore_files_and_include_globs() {
+		let dir = tempfile::tempdir().unwrap();
+		let write = |path: &str, contents: &str| {
+			let path = dir.path().join(path);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, contents).unwrap();
+		};
+		write(".gitignore", "*.log\nbuild/\n/kept.txt\n");
+		write(".ignore", "!kept.txt\n");
+		write("app.log", "");
+		write("build/app.js", "");
+		write("kept.txt", "");
+		write("docs/.gitignore", "/draft.md\n!important.log\n");
+		write("docs/draft.md", "");
+		write("docs/important.log", "");
+		write("docs/index.md", "");
+		write("docs/nested/draft.md", "");
+		let fs_directory = FsDirectory(dir.path().to_owned(), PathFilter::default());
+		assert_eq!(
+			fs_directory.paths(),
+			vec![
+				PathBuf::from("docs/important.log"),
+				PathBuf::from("docs/index.md"),
+				PathBuf::from("docs/nested/draft.md"),
+				PathBuf::from("kept.txt"),
+			]
+		);
+		// Files an include glob matches are kept even if an ignore file leaves them out.
+		let filter = PathFilter::new(dir.path(), &["*.log"], &[]).unwrap();
+		let fs_directory = FsDirectory(dir.path().to_owned(), filter);
+		assert_eq!(
+			fs_directory.paths(),
+			vec![
+				PathBuf::from("app.log"),
+				PathBuf::from("docs/important.log")
+			]
+		);
+	}
+
+	#[test]
+	fn prefixes_of_gzipped_files_are_decompressed_lazily() {
+		let data = b"hello, world".repeat(100);
+		let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+		std::io::Write::write_all(&mut encoder, &data).unwrap();
+		let gzip: &'static [u8] = Box::leak(encoder.finish().unwrap().into_boxed_slice());
+		let decompressed: &'static OnceCell<Vec<u8>> = Box::leak(Box::new(OnceCell::new()));
+		let file = IncludedFile {
+			data: IncludedData::Gzip {
+				gzip,
+				size: data.len(),
+				decompressed,
+			},
+			hash: "",
+			full_hash: "",
+			gzip: Some(gzip),
+			modified: 0,
+		};
+		assert_eq!(file.prefix(5).as_ref(), b"hello");
+		assert!(decompressed.get().is_none());
+		assert_eq!(file.data().as_ref(), data.as_slice());
+		assert_eq!(file.prefix(5).as_ref(), b"hello");
+		assert_eq!(file.prefix(usize::MAX).len(), data.len());
+	}
+
+	#[test]
+	fn reading_a_deleted_file_is_an_error() {
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::write(dir.path().join("app.css"), "body {}").unwrap();
+		let fs_directory = FsDirectory(dir.path().to_owned(), PathFilter::default());
+		let file = fs_directory.read(Path::new("app.css")).unwrap().unwrap();
+		assert_eq!(file.data().unwrap().as_ref(), b"body {}");
+		std::fs::remove_file(dir.path().join("app.css")).unwrap();
+		assert_eq!(
+			file.data().unwrap_err().kind(),
+			std::io::ErrorKind::NotFound
+		);
+	}
 }
@@ -1,26 +1,76 @@
-pub use self::builder::{build, BuildOptions};
+pub use self::{
+	asset_manifest::ASSET_MANIFEST_PATH,
+	bot::{is_bot, is_bot_with_user_agents, DEFAULT_BOT_USER_AGENTS},
+	builder::{build, BuildOptions, Bundle},
+	clear_site_data::{clear_site_data_response, ClearSiteDataScope},
+	export::{
+		ExportMode, ExportOptions, RedirectFile, SitemapFormat, UrlStyle, EXPORT_CONFIG_FILE_NAME,
+		SITEMAP_MAX_BYTES, SITEMAP_MAX_URLS,
+	},
+	network_error_logging::NetworkErrorLogging,
+	serve::SunfishService,
+};
 use anyhow::Result;
+use asset_manifest::AssetManifest;
 use digest::Digest;
 use futures::FutureExt;
-use ignore::Walk;
-use include_dir::IncludeDir;
-use std::{future::Future, path::Path, pin::Pin};
+use include_dir::{FsDirectory, FsOrIncludedFile, IncludeDir, PathFilter};
+use once_cell::sync::OnceCell;
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	future::Future,
+	path::{Path, PathBuf},
+	pin::Pin,
+	sync::Arc,
+};
 pub use sunfish_macro::{include_dir, init};
 
+mod asset_manifest;
+mod bot;
 mod builder;
+mod cache_groups;
+mod clear_site_data;
+mod conditional;
+#[cfg(debug_assertions)]
+mod echo;
+mod export;
 pub mod include_dir;
+#[cfg(feature = "multipart")]
+pub mod multipart;
+mod network_error_logging;
+mod range;
+pub mod response;
+mod serve;
 pub mod watchserve;
 
 pub enum Route {
 	Static {
 		paths: Option<Box<dyn 'static + Send + Sync + Fn() -> Vec<String>>>,
-		handler: Box<dyn 'static + Send + Sync + Fn(String) -> String>,
+		handler: StaticHandler,
+		options: RouteOptions,
 	},
 	Dynamic {
 		handler: DynamicHandler,
+		/// If set, export renders this route's pages like a static route's.
+		prerender: Option<Prerender>,
+		options: RouteOptions,
 	},
 }
 
+/// The paths and renderer export uses to prerender a dynamic route.
+pub struct Prerender {
+	pub paths: Box<dyn 'static + Send + Sync + Fn() -> Vec<String>>,
+	/// Renders each page. If this is `None`, export handles a `GET` request for each path with the route's handler instead and writes the body of the response, which must be a `200`. Those requests are handled on a runtime export creates, so the handler must not depend on resources tied to the caller's runtime.
+	pub render: Option<StaticHandler>,
+}
+
+/// Renders the page at a path. Returning an error responds with a `500`, or with the page from `Sunfish::with_error_handler`, and fails export.
+pub type StaticHandler = Box<dyn 'static + Send + Sync + Fn(String) -> Result<String>>;
+
+/// Renders the page for a request to a path that failed with an error.
+pub type ErrorHandler = Box<dyn 'static + Send + Sync + Fn(String, &anyhow::Error) -> String>;
+
 pub type DynamicHandler = Box<
 	dyn Send + Sync + for<'a> Fn(&'a mut http::Request<hyper::Body>) -> DynamicHandlerOutput<'a>,
 >;
@@ -28,14 +78,82 @@ pub type DynamicHandler = Box<
 pub type DynamicHandlerOutput<'a> =
 	Pin<Box<dyn 'a + Send + Future<Output = Result<http::Response<hyper::Body>>>>>;
 
+#[derive(Clone, Default)]
+pub struct RouteOptions {
+	/// The names of the cookies this route's output depends on. If this is not empty, responses are sent with `Vary: Cookie` and `Cache-Control: private` so that shared caches never serve one user's page to another.
+	pub vary_cookies: Vec<String>,
+	/// Maps a request path to the path of the canonical URL for the page. The canonical URL is the configured base URL followed by this path.
+	pub canonical_path: Option<CanonicalPathFn>,
+	/// A route to handle requests from bots instead of this one, for example to serve a fully rendered page to crawlers and a lighter shell to everyone else. If this is set, responses are sent with `Vary: User-Agent`.
+	pub bot_variant: Option<Arc<Route>>,
+	/// The `User-Agent` substrings that identify a bot for `bot_variant`. Defaults to `DEFAULT_BOT_USER_AGENTS`.
+	pub bot_user_agents: Option<Vec<String>>,
+	/// How clients may cache this route's successful responses. Error responses are left alone, so that they are never cached as `immutable`. If this is not set, no `Cache-Control` header is added.
+	pub cache_policy: Option<CachePolicy>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachePolicy {
+	/// Do not store the response at all: `Cache-Control: no-store`.
+	NoStore,
+	/// Store the response but revalidate it on every use: `Cache-Control: no-cache, must-revalidate`, with an `ETag` from the hash of the body if the handler did not set one, so that revalidation gets a `304` when the page has not changed.
+	Revalidate,
+	/// Store the response and never revalidate it: `Cache-Control: public, max-age=31536000, immutable`.
+	Immutable,
+}
+
+/// Which responses a cache policy registered with `Sunfish::with_cache_rule` applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheRule {
+	/// Responses to requests whose path starts with this prefix, such as `/assets/`.
+	PathPrefix(String),
+	/// Responses with this content type, ignoring parameters, such as `text/html`. Pages without a content type are HTML.
+	ContentType(String),
+}
+
+/// A redirect registered with `Sunfish::with_redirect`. `handle` serves it, and export can write it to the redirect files of static hosts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redirect {
+	/// The path to redirect from, such as `/old`. Only requests for exactly this path match.
+	pub from: String,
+	/// The path or URL to redirect to.
+	pub to: String,
+	/// Whether the redirect is sent with `301 Moved Permanently` instead of `302 Found`.
+	pub permanent: bool,
+}
+
+/// Runs on every request before it is handled. Returning a response sends it instead of handling the request.
+pub type RequestHook = Box<
+	dyn Send + Sync + Fn(&mut http::Request<hyper::Body>) -> Option<http::Response<hyper::Body>>,
+>;
+
+/// Runs on every response `Sunfish::handle` sends, with the request it answers.
+pub type ResponseHook =
+	Box<dyn Send + Sync + Fn(&http::Request<hyper::Body>, &mut http::Response<hyper::Body>)>;
+
+pub type CanonicalPathFn = Arc<dyn Send + Sync + Fn(&str) -> String>;
+
+/// The canonical path of a page, attached to a response's extensions by `Route::handle`.
+#[derive(Clone, Debug)]
+pub struct CanonicalPath(pub String);
+
 impl Route {
 	pub fn new_static<H>(handler: H) -> Route
 	where
 		H: 'static + Send + Sync + Fn(String) -> String,
+	{
+		Route::new_fallible_static(move |path| Ok(handler(path)))
+	}
+
+	/// Create a static route whose handler can fail.
+	pub fn new_fallible_static<H>(handler: H) -> Route
+	where
+		H: 'static + Send + Sync + Fn(String) -> Result<String>,
 	{
 		Route::Static {
 			paths: None,
 			handler: Box::new(handler),
+			options: RouteOptions::default(),
 		}
 	}
 
@@ -43,10 +161,20 @@ impl Route {
 	where
 		P: 'static + Send + Sync + Fn() -> Vec<String>,
 		H: 'static + Send + Sync + Fn(String) -> String,
+	{
+		Route::new_fallible_static_with_paths(paths, move |path| Ok(handler(path)))
+	}
+
+	/// Create a static route with paths whose handler can fail.
+	pub fn new_fallible_static_with_paths<P, H>(paths: P, handler: H) -> Route
+	where
+		P: 'static + Send + Sync + Fn() -> Vec<String>,
+		H: 'static + Send + Sync + Fn(String) -> Result<String>,
 	{
 		Route::Static {
 			paths: Some(Box::new(paths)),
 			handler: Box::new(handler),
+			options: RouteOptions::default(),
 		}
 	}
 
@@ -59,6 +187,97 @@ impl Route {
 	{
 		Route::Dynamic {
 			handler: Box::new(handler),
+			prerender: None,
+			options: RouteOptions::default(),
+		}
+	}
+
+	/// Create a dynamic route that export also prerenders. `handler` serves requests at runtime, and export writes the page `render` returns for each path `paths` returns. Only use this for routes whose pages do not depend on anything in the live request.
+	pub fn new_dynamic_prerenderable<P, R, H>(paths: P, render: R, handler: H) -> Route
+	where
+		P: 'static + Send + Sync + Fn() -> Vec<String>,
+		R: 'static + Send + Sync + Fn(String) -> String,
+		H: 'static
+			+ Send
+			+ Sync
+			+ for<'a> Fn(&'a mut http::Request<hyper::Body>) -> DynamicHandlerOutput<'a>,
+	{
+		Route::Dynamic {
+			handler: Box::new(handler),
+			prerender: Some(Prerender {
+				paths: Box::new(paths),
+				render: Some(Box::new(move |path| Ok(render(path)))),
+			}),
+			options: RouteOptions::default(),
+		}
+	}
+
+	/// Create a dynamic route that export also prerenders by handling a `GET` request for each path `paths` returns with `handler`. The requests have no headers or body, so only use this for routes whose pages depend on nothing in the request but its path.
+	pub fn new_dynamic_with_prerender<P, H>(paths: P, handler: H) -> Route
+	where
+		P: 'static + Send + Sync + Fn() -> Vec<String>,
+		H: 'static
+			+ Send
+			+ Sync
+			+ for<'a> Fn(&'a mut http::Request<hyper::Body>) -> DynamicHandlerOutput<'a>,
+	{
+		Route::Dynamic {
+			handler: Box::new(handler),
+			prerender: Some(Prerender {
+				paths: Box::new(paths),
+				render: None,
+			}),
+			options: RouteOptions::default(),
+		}
+	}
+
+	/// Declare that this route's output depends on the cookie named `name`.
+	pub fn vary_by_cookie(mut self, name: impl Into<String>) -> Route {
+		self.options_mut().vary_cookies.push(name.into());
+		self
+	}
+
+	/// Send a `Link: <url>; rel="canonical"` header with this route's responses, where `url` is the base URL followed by the path returned by `canonical_path`. During export, a `<link rel="canonical">` element is added to the page instead.
+	pub fn with_canonical_path<F>(mut self, canonical_path: F) -> Route
+	where
+		F: 'static + Send + Sync + Fn(&str) -> String,
+	{
+		self.options_mut().canonical_path = Some(Arc::new(canonical_path));
+		self
+	}
+
+	/// Handle requests from bots, as detected by `is_bot`, with `bot_variant` instead of this route.
+	pub fn with_bot_variant(mut self, bot_variant: Route) -> Route {
+		self.options_mut().bot_variant = Some(Arc::new(bot_variant));
+		self
+	}
+
+	/// Identify bots for `with_bot_variant` by these `User-Agent` substrings instead of `DEFAULT_BOT_USER_AGENTS`.
+	pub fn with_bot_user_agents<I, S>(mut self, bot_user_agents: I) -> Route
+	where
+		I: IntoIterator<Item = S>,
+		S: Into<String>,
+	{
+		self.options_mut().bot_user_agents =
+			Some(bot_user_agents.into_iter().map(Into::into).collect());
+		self
+	}
+
+	/// Set how clients may cache this route's successful responses.
+	pub fn with_cache_policy(mut self, cache_policy: CachePolicy) -> Route {
+		self.options_mut().cache_policy = Some(cache_policy);
+		self
+	}
+
+	pub fn options(&self) -> &RouteOptions {
+		match self {
+			Route::Static { options, .. } | Route::Dynamic { options, .. } => options,
+		}
+	}
+
+	fn options_mut(&mut self) -> &mut RouteOptions {
+		match self {
+			Route::Static { options, .. } | Route::Dynamic { options, .. } => options,
 		}
 	}
 
@@ -66,31 +285,215 @@ impl Route {
 		&self,
 		request: &'a mut http::Request<hyper::Body>,
 	) -> DynamicHandlerOutput<'a> {
-		match self {
-			Route::Static { handler, .. } => {
+		let canonical_path = self
+			.options()
+			.canonical_path
+			.as_ref()
+			.map(|canonical_path| canonical_path(request.uri().path()));
+		let bot_variant =
+			self.options()
+				.bot_variant
+				.as_ref()
+				.filter(|_| match &self.options().bot_user_agents {
+					Some(bot_user_agents) => is_bot_with_user_agents(request, bot_user_agents),
+					None => is_bot(request),
+				});
+		let response = match (self, bot_variant) {
+			(_, Some(bot_variant)) => bot_variant.handle(request),
+			(Route::Static { handler, .. }, None) => {
 				let html = handler(request.uri().path().to_owned());
 				async {
+					let html = html?;
 					let response = http::Response::builder()
 						.status(http::StatusCode::OK)
+						.header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
 						.body(hyper::Body::from(html))
 						.unwrap();
 					Ok(response)
 				}
 				.boxed()
 			}
-			Route::Dynamic { handler } => handler(request),
+			(Route::Dynamic { handler, .. }, None) => handler(request),
+		};
+		let options = self.options().clone();
+		async move {
+			let mut response = response.await?;
+			if let Some(cache_policy) = options.cache_policy {
+				if response.status() == http::StatusCode::OK {
+					response = apply_cache_policy(cache_policy, response).await?;
+				}
+			}
+			if !options.vary_cookies.is_empty() {
+				make_private(&mut response);
+			}
+			if options.bot_variant.is_some() {
+				response.headers_mut().append(
+					http::header::VARY,
+					http::HeaderValue::from_static("User-Agent"),
+				);
+			}
+			if let Some(canonical_path) = canonical_path {
+				response
+					.extensions_mut()
+					.insert(CanonicalPath(canonical_path));
+			}
+			Ok(response)
 		}
+		.boxed()
+	}
+}
+
+async fn apply_cache_policy(
+	cache_policy: CachePolicy,
+	response: http::Response<hyper::Body>,
+) -> Result<http::Response<hyper::Body>> {
+	let (mut parts, body) = response.into_parts();
+	let cache_control = match cache_policy {
+		CachePolicy::NoStore => "no-store",
+		CachePolicy::Revalidate => "no-cache, must-revalidate",
+		CachePolicy::Immutable => "public, max-age=31536000, immutable",
+	};
+	parts.headers.insert(
+		http::header::CACHE_CONTROL,
+		http::HeaderValue::from_static(cache_control),
+	);
+	if cache_policy != CachePolicy::Revalidate || parts.headers.contains_key(http::header::ETAG) {
+		return Ok(http::Response::from_parts(parts, body));
 	}
+	// Buffer the body to hash it for the ETag.
+	let body = hyper::body::to_bytes(body).await?;
+	let etag = format!("\"{}\"", hash(&body));
+	parts
+		.headers
+		.insert(http::header::ETAG, http::HeaderValue::from_str(&etag)?);
+	Ok(http::Response::from_parts(parts, hyper::Body::from(body)))
+}
+
+/// Mark a response as varying by cookie and forbid shared caches from storing it.
+fn make_private(response: &mut http::Response<hyper::Body>) {
+	let headers = response.headers_mut();
+	headers.append(http::header::VARY, http::HeaderValue::from_static("Cookie"));
+	let cache_control = headers
+		.get(http::header::CACHE_CONTROL)
+		.and_then(|value| value.to_str().ok())
+		.unwrap_or("");
+	let directives = cache_control
+		.split(',')
+		.map(|directive| directive.trim())
+		.filter(|directive| !directive.is_empty());
+	if directives.clone().any(|directive| {
+		directive.eq_ignore_ascii_case("private") || directive.eq_ignore_ascii_case("no-store")
+	}) {
+		return;
+	}
+	let directives = std::iter::once("private")
+		.chain(directives.filter(|directive| {
+			!directive.eq_ignore_ascii_case("public")
+				&& !directive.to_ascii_lowercase().starts_with("s-maxage")
+		}))
+		.collect::<Vec<_>>()
+		.join(", ");
+	headers.insert(
+		http::header::CACHE_CONTROL,
+		http::HeaderValue::from_str(&directives).unwrap(),
+	);
 }
 
 pub fn path_components(path: &str) -> Vec<&str> {
 	path.split('/').skip(1).collect::<Vec<_>>()
 }
 
+/// The values of the placeholders in a route's path, such as `repo_id` in `/repos/_repo_id/edit`. The routes handler generated by `init!` attaches these to each request it routes as an extension.
+#[derive(Clone, Debug, Default)]
+pub struct PathParams(Vec<(String, String)>);
+
+impl PathParams {
+	/// Match `path` against `path_with_placeholders`, where each component that starts with `_` is a placeholder named by the rest of the component. Values are percent-decoded.
+	pub fn new(path_with_placeholders: &str, path: &str) -> PathParams {
+		let params = path_components(path_with_placeholders)
+			.into_iter()
+			.zip(path_components(path))
+			.filter_map(|(placeholder, value)| {
+				let name = placeholder.strip_prefix('_')?;
+				let value = percent_decode(value, true)?;
+				Some((name.to_owned(), value))
+			})
+			.collect();
+		PathParams(params)
+	}
+
+	/// Get the params attached to `request`, if it was routed by the routes handler.
+	pub fn from_request(request: &http::Request<hyper::Body>) -> Option<&PathParams> {
+		request.extensions().get::<PathParams>()
+	}
+
+	/// Get the value of the placeholder named `name`.
+	pub fn get_str(&self, name: &str) -> Option<&str> {
+		self.0
+			.iter()
+			.find(|(param_name, _)| param_name == name)
+			.map(|(_, value)| value.as_str())
+	}
+
+	/// Get the value of the placeholder named `name` parsed as a `T`. Returns `None` if there is no such placeholder or its value does not parse.
+	pub fn get<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+		self.get_str(name)?.parse().ok()
+	}
+}
+
+/// Percent-decode a request path. Encoded slashes (`%2F`) are left encoded so that they can never introduce a path separator. Returns `None` if the path contains a malformed escape or does not decode to valid UTF-8.
+pub fn percent_decode_path(path: &str) -> Option<String> {
+	percent_decode(path, false)
+}
+
+fn percent_decode(value: &str, decode_slashes: bool) -> Option<String> {
+	let bytes = value.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while let Some(&byte) = bytes.get(i) {
+		if byte != b'%' {
+			decoded.push(byte);
+			i += 1;
+			continue;
+		}
+		let high = bytes.get(i + 1).copied().and_then(hex_value)?;
+		let low = bytes.get(i + 2).copied().and_then(hex_value)?;
+		let byte = (high << 4) | low;
+		if byte == b'/' && !decode_slashes {
+			decoded.extend_from_slice(&bytes[i..i + 3]);
+		} else {
+			decoded.push(byte);
+		}
+		i += 3;
+	}
+	String::from_utf8(decoded).ok()
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+	match byte {
+		b'0'..=b'9' => Some(byte - b'0'),
+		b'a'..=b'f' => Some(byte - b'a' + 10),
+		b'A'..=b'F' => Some(byte - b'A' + 10),
+		_ => None,
+	}
+}
+
+/// Get the path an asset is served at. Assets without an extension are served without one.
 pub fn asset_path(path: &Path) -> String {
-	let extension = path.extension().map(|e| e.to_str().unwrap()).unwrap();
-	let hash = hash(&path.to_str().unwrap().as_bytes());
-	format!("/assets/{}.{}", hash, extension)
+	let hash = hash(path.to_string_lossy().as_bytes());
+	match path.extension() {
+		Some(extension) => format!("/assets/{}.{}", hash, extension.to_string_lossy()),
+		None => format!("/assets/{}", hash),
+	}
+}
+
+/// Get the path the bundle named `name` with the concatenated contents `contents` is served at. The path is named by the hash of the contents, so it changes whenever a constituent does. Pages get it from `Sunfish::bundle_url`, which reads the asset manifest.
+pub fn bundle_path(name: &str, contents: &[u8]) -> String {
+	let hash = hash(contents);
+	match Path::new(name).extension() {
+		Some(extension) => format!("/assets/{}.{}", hash, extension.to_string_lossy()),
+		None => format!("/assets/{}", hash),
+	}
 }
 
 pub struct ClientPaths {
@@ -106,79 +509,407 @@ pub fn client_paths(crate_name: &'static str) -> ClientPaths {
 	}
 }
 
-type RoutesHandler = Box<
+pub type RoutesHandler = Box<
 	dyn Send + Sync + for<'a> Fn(&'a mut http::Request<hyper::Body>) -> RoutesHandlerOutput<'a>,
 >;
 
-type RoutesHandlerOutput<'a> =
+pub type RoutesHandlerOutput<'a> =
 	Pin<Box<dyn 'a + Send + Future<Output = Result<Option<http::Response<hyper::Body>>>>>>;
 
 pub struct Sunfish {
 	pub output: IncludeDir,
 	pub routes_handler: RoutesHandler,
 	pub routes: Vec<RouteInitializer>,
+	/// The URL the site is served from, for example `https://example.com`, used to build absolute URLs such as canonical links.
+	pub base_url: Option<String>,
+	/// The language of the site's pages, such as `en` or `pt-BR`. When set, page responses that are HTML and do not already have a `Content-Language` header get one with this value.
+	pub default_language: Option<String>,
+	/// Whether every file in the output is also served at `/cas/<full hash of its contents>`.
+	pub content_addressed_assets: bool,
+	/// Whether assets use the full hash of their contents as their `ETag` instead of the truncated hash. The truncated hash keeps `ETag`s short, but across very large sets of assets a collision could cause a wrong `304`.
+	pub full_hash_etags: bool,
+	/// Whether assets are sent with `Repr-Digest` and `Digest` headers carrying the hash of the bytes they send, which for gzipped responses is the hash of the gzipped contents, so clients can verify the body. Only `sha256` and `sha512` have registered digest names, so no header is sent for other values of `HASH_ALGORITHM`.
+	pub digest_headers: bool,
+	/// How requests for `/` are served.
+	pub root: RootPath,
+	/// The path of a file in the output, usually `index.html`, to serve for `GET` requests that match no page or asset, so that a single page application can route them on the client.
+	pub spa_fallback: Option<PathBuf>,
+	/// Methods that `handle` answers with `405 Method Not Allowed` before any route sees the request. Defaults to `TRACE` and `TRACK`, which can enable cross-site tracing attacks.
+	pub rejected_methods: Vec<http::Method>,
+	/// If set, `handle` answers requests without an `Authorization: Bearer <token>` header carrying this token with `401 Unauthorized`. This is meant to keep staging deployments private, not to replace real authentication.
+	pub bearer_token: Option<String>,
+	/// Paths that are served without the bearer token, such as health checks.
+	pub bearer_token_allowlist: Vec<String>,
+	/// A Network Error Logging policy to send with every response.
+	pub network_error_logging: Option<NetworkErrorLogging>,
+	/// A directory whose files are served in place of the files at the same paths in the output, for example to override a few assets per environment without rebuilding.
+	pub overlay: Option<FsDirectory>,
+	/// Maps lowercase file extensions, without the leading dot, to content types. These take precedence over the built in table.
+	pub content_types: HashMap<String, String>,
+	/// Whether assets without an extension get a content type guessed from their leading bytes. Only PNG, JPEG, GIF, PDF, and UTF-8 text are recognized. Responses with a guessed type are sent with `X-Content-Type-Options: nosniff` so that browsers do not guess differently.
+	pub content_sniffing: bool,
+	/// Cache policies for pages and assets, checked in order. The first rule that matches a successful response without a `Cache-Control` header applies, so a route's own `cache_policy` and the `immutable` policy of hashed assets take precedence.
+	pub cache_rules: Vec<(CacheRule, CachePolicy)>,
+	/// Redirects that `handle` serves before any page or asset. The first one whose `from` matches the request's path applies.
+	pub redirects: Vec<Redirect>,
+	/// Hooks that run in order on every request before any page or asset is served.
+	pub request_hooks: Vec<RequestHook>,
+	/// Hooks that run in order on every response, including those from request hooks.
+	pub response_hooks: Vec<ResponseHook>,
+	/// Renders the page sent with `404` responses, given the request's path. Export also writes it to `404.html`.
+	pub not_found_handler: Option<StaticHandler>,
+	/// Renders the page sent with `500` responses when handling a request fails.
+	pub error_handler: Option<ErrorHandler>,
+	/// Whether asset misses get a diagnostic `404` explaining that the output directory is missing, when it is an `Fs` directory that does not exist. Defaults to on in debug builds.
+	pub asset_dir_diagnostics: bool,
+	/// Maps full content hashes to paths in the output. This is only built for included directories, because files in an `Fs` directory can change while the server runs.
+	cas_index: Option<HashMap<String, PathBuf>>,
+	/// The asset manifest, parsed the first time it is read.
+	asset_manifest: OnceCell<Option<AssetManifest>>,
+}
+
+/// How `Sunfish::handle` serves requests for `/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RootPath {
+	/// Serve the route registered at `/`, falling back to `index.html` in the output if there is none.
+	RouteOrIndex,
+	/// Serve only the route registered at `/`.
+	Route,
+	/// Serve `index.html` from the output, even if a route is registered at `/`.
+	Index,
+	/// Redirect to this path.
+	Redirect(String),
 }
 
 pub struct RouteInitializer {
 	pub path_with_placeholders: String,
 	pub init: fn() -> Route,
+	/// The package name of the client crate next to the route's server crate, if it has one.
+	pub client_crate_name: Option<&'static str>,
+}
+
+impl RouteInitializer {
+	/// Whether `path` matches this route's path, where each component that starts with `_` matches any value.
+	pub fn matches(&self, path: &str) -> bool {
+		let route_path_components = path_components(&self.path_with_placeholders);
+		let path_components = path_components(path);
+		route_path_components.len() == path_components.len()
+			&& route_path_components.iter().zip(path_components).all(
+				|(route_path_component, path_component)| {
+					route_path_component.starts_with('_') || *route_path_component == path_component
+				},
+			)
+	}
 }
 
 impl Sunfish {
-	pub fn export(&self, out_dir: &Path, dist_path: &Path) -> Result<()> {
-		let output_path = out_dir.join("output");
-		// Create a new directory at dist_path.
-		if std::fs::metadata(&dist_path).is_ok() {
-			std::fs::remove_dir_all(&dist_path)?;
-		}
-		std::fs::create_dir_all(&dist_path)?;
-		// Copy the contents of the out_dir to the dist_path.
-		for entry in Walk::new(&output_path) {
-			let entry = entry.unwrap();
-			let input_path = entry.path();
-			if !input_path.is_file() {
-				continue;
-			}
-			let path = input_path.strip_prefix(&output_path).unwrap();
-			let output_path = dist_path.join(path);
-			std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
-			std::fs::copy(&input_path, &output_path).unwrap();
-		}
-		// Render and write the html for each page.
-		for route in self.routes.iter() {
-			match (route.init)() {
-				Route::Static { paths, handler } => {
-					let paths = paths
-						.map(|paths| paths())
-						.unwrap_or_else(|| vec![route.path_with_placeholders.clone()]);
-					for path in paths {
-						let output_html_path = match path.as_str() {
-							"/" => "/index.html".to_owned(),
-							path if path.ends_with('/') => format!("{}index.html", path),
-							path => format!("{}.html", path),
-						};
-						let output_html_path =
-							dist_path.join(&output_html_path.strip_prefix('/').unwrap());
-						let html = handler(path);
-						std::fs::create_dir_all(output_html_path.parent().unwrap()).unwrap();
-						std::fs::write(&output_html_path, html)?;
-					}
-				}
-				Route::Dynamic { .. } => continue,
+	pub fn new(
+		output: IncludeDir,
+		routes_handler: RoutesHandler,
+		routes: Vec<RouteInitializer>,
+	) -> Sunfish {
+		if let IncludeDir::Fs(fs_directory) = &output {
+			if !fs_directory.root_exists() {
+				tracing::warn!(
+					path = %fs_directory.0.display(),
+					"the asset directory does not exist, so no assets will be served. Was the project moved after it was built?"
+				);
 			}
 		}
-		Ok(())
+		Sunfish {
+			output,
+			routes_handler,
+			routes,
+			base_url: None,
+			default_language: None,
+			content_addressed_assets: false,
+			full_hash_etags: false,
+			digest_headers: false,
+			root: RootPath::RouteOrIndex,
+			spa_fallback: None,
+			rejected_methods: default_rejected_methods(),
+			bearer_token: None,
+			bearer_token_allowlist: Vec::new(),
+			network_error_logging: None,
+			overlay: None,
+			content_types: HashMap::new(),
+			content_sniffing: false,
+			cache_rules: Vec::new(),
+			redirects: Vec::new(),
+			request_hooks: Vec::new(),
+			response_hooks: Vec::new(),
+			not_found_handler: None,
+			error_handler: None,
+			asset_dir_diagnostics: cfg!(debug_assertions),
+			cas_index: None,
+			asset_manifest: OnceCell::new(),
+		}
+	}
+
+	pub fn with_base_url(mut self, base_url: impl Into<String>) -> Sunfish {
+		self.base_url = Some(base_url.into());
+		self
+	}
+
+	pub fn with_default_language(mut self, default_language: impl Into<String>) -> Sunfish {
+		self.default_language = Some(default_language.into());
+		self
+	}
+
+	/// Also serve every file in the output at `/cas/<full hash>`, where the hash is the full hex-encoded hash of the file's contents, with a `Cache-Control` header marking it immutable.
+	pub fn with_content_addressed_assets(mut self) -> Sunfish {
+		self.content_addressed_assets = true;
+		if let IncludeDir::Included(included_directory) = &self.output {
+			let cas_index = included_directory
+				.0
+				.iter()
+				.map(|(path, file)| (file.full_hash.to_owned(), path.to_path_buf()))
+				.collect();
+			self.cas_index = Some(cas_index);
+		}
+		self
+	}
+
+	/// Use the full hash of each asset's contents as its `ETag`.
+	pub fn with_full_hash_etags(mut self) -> Sunfish {
+		self.full_hash_etags = true;
+		self
+	}
+
+	/// Send `Repr-Digest` and `Digest` headers with assets.
+	pub fn with_digest_headers(mut self) -> Sunfish {
+		self.digest_headers = true;
+		self
+	}
+
+	/// Choose how requests for `/` are served. Defaults to `RootPath::RouteOrIndex`.
+	pub fn with_root(mut self, root: RootPath) -> Sunfish {
+		self.root = root;
+		self
+	}
+
+	/// Serve the file at `path` in the output for `GET` requests that match no page or asset and whose path has no extension. The response has an `ETag` from the file's hash, so clients can revalidate it with `If-None-Match`.
+	pub fn with_spa_fallback(mut self, path: impl Into<PathBuf>) -> Sunfish {
+		self.spa_fallback = Some(path.into());
+		self
+	}
+
+	/// Answer requests with these methods with `405 Method Not Allowed`, replacing the default `TRACE` and `TRACK`.
+	pub fn with_rejected_methods(mut self, rejected_methods: Vec<http::Method>) -> Sunfish {
+		self.rejected_methods = rejected_methods;
+		self
+	}
+
+	/// Require every request, except those for paths in `allowlist`, to carry `token` as a bearer token.
+	pub fn with_bearer_token(
+		mut self,
+		token: impl Into<String>,
+		allowlist: Vec<String>,
+	) -> Sunfish {
+		self.bearer_token = Some(token.into());
+		self.bearer_token_allowlist = allowlist;
+		self
+	}
+
+	/// Like `with_bearer_token`, reading the token from the environment variable `name`. If the variable is not set or is empty, requests are not gated.
+	pub fn with_bearer_token_from_env(self, name: &str, allowlist: Vec<String>) -> Sunfish {
+		match std::env::var(name) {
+			Ok(token) if !token.is_empty() => self.with_bearer_token(token, allowlist),
+			_ => self,
+		}
+	}
+
+	pub fn with_network_error_logging(
+		mut self,
+		network_error_logging: NetworkErrorLogging,
+	) -> Sunfish {
+		self.network_error_logging = Some(network_error_logging);
+		self
+	}
+
+	/// Serve files from `overlay` in place of the files at the same paths in the output. Overlaid files get `ETag`s from the hash of their contents.
+	pub fn with_overlay(mut self, overlay: impl Into<PathBuf>) -> Sunfish {
+		self.overlay = Some(FsDirectory(overlay.into(), PathFilter::default()));
+		self
+	}
+
+	/// Like `with_overlay`, reading the overlay's path from the environment variable `name`. If the variable is not set or is empty, there is no overlay.
+	pub fn with_overlay_from_env(self, name: &str) -> Sunfish {
+		match std::env::var(name) {
+			Ok(overlay) if !overlay.is_empty() => self.with_overlay(overlay),
+			_ => self,
+		}
+	}
+
+	/// Serve files with the extension `extension` with the content type `content_type`, overriding the built in table. Export uses the same content types when it writes a `_headers` file.
+	pub fn with_content_type(
+		mut self,
+		extension: impl Into<String>,
+		content_type: impl Into<String>,
+	) -> Sunfish {
+		let extension = extension.into();
+		let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+		self.content_types.insert(extension, content_type.into());
+		self
+	}
+
+	/// Guess the content type of assets without an extension from their leading bytes.
+	pub fn with_content_sniffing(mut self) -> Sunfish {
+		self.content_sniffing = true;
+		self
+	}
+
+	/// Apply `cache_policy` to the responses `cache_rule` matches, after any rules added before it. For example, `with_cache_rule(CacheRule::ContentType("text/html".to_owned()), CachePolicy::Revalidate)` makes browsers revalidate every page.
+	pub fn with_cache_rule(mut self, cache_rule: CacheRule, cache_policy: CachePolicy) -> Sunfish {
+		self.cache_rules.push((cache_rule, cache_policy));
+		self
+	}
+
+	/// Redirect requests for `from` to `to`, permanently if `permanent` is set. Export writes the same redirects to `_redirects` or `vercel.json` when `ExportOptions::redirect_files` asks for them.
+	pub fn with_redirect(
+		mut self,
+		from: impl Into<String>,
+		to: impl Into<String>,
+		permanent: bool,
+	) -> Sunfish {
+		self.redirects.push(Redirect {
+			from: from.into(),
+			to: to.into(),
+			permanent,
+		});
+		self
+	}
+
+	/// Run `hook` on every request, after any hooks added before it, for example to check authorization or to attach a request ID. If it returns a response, that response is sent and the request is not handled further. Hooks can store values, such as the time the request started, in the request's extensions for response hooks to read.
+	pub fn with_request_hook(
+		mut self,
+		hook: impl 'static
+			+ Send
+			+ Sync
+			+ Fn(&mut http::Request<hyper::Body>) -> Option<http::Response<hyper::Body>>,
+	) -> Sunfish {
+		self.request_hooks.push(Box::new(hook));
+		self
+	}
+
+	/// Run `hook` on every response, after any hooks added before it, for example to add security headers such as `X-Frame-Options` or to log timing.
+	pub fn with_response_hook(
+		mut self,
+		hook: impl 'static
+			+ Send
+			+ Sync
+			+ Fn(&http::Request<hyper::Body>, &mut http::Response<hyper::Body>),
+	) -> Sunfish {
+		self.response_hooks.push(Box::new(hook));
+		self
+	}
+
+	/// Respond to requests that no page or asset matches with the page `handler` renders for the request's path. If rendering fails, the plain `404` is sent.
+	pub fn with_not_found_handler(
+		mut self,
+		handler: impl 'static + Send + Sync + Fn(String) -> Result<String>,
+	) -> Sunfish {
+		self.not_found_handler = Some(Box::new(handler));
+		self
+	}
+
+	/// Respond to requests whose handling fails with the page `handler` renders for the request's path and the error.
+	pub fn with_error_handler(
+		mut self,
+		handler: impl 'static + Send + Sync + Fn(String, &anyhow::Error) -> String,
+	) -> Sunfish {
+		self.error_handler = Some(Box::new(handler));
+		self
+	}
+
+	pub fn with_asset_dir_diagnostics(mut self, asset_dir_diagnostics: bool) -> Sunfish {
+		self.asset_dir_diagnostics = asset_dir_diagnostics;
+		self
 	}
 
 	pub async fn handle(
 		&self,
 		request: &mut http::Request<hyper::Body>,
 	) -> Result<Option<http::Response<hyper::Body>>> {
-		let response = self.serve_page(request).await?;
+		let response = self
+			.request_hooks
+			.iter()
+			.find_map(|request_hook| request_hook(request));
+		let mut response = match response {
+			Some(response) => Some(response),
+			None => self.handle_request(request).await?,
+		};
+		if let Some(response) = response.as_mut() {
+			if let Some(network_error_logging) = &self.network_error_logging {
+				network_error_logging.apply(response.headers_mut());
+			}
+			for response_hook in self.response_hooks.iter() {
+				response_hook(request, response);
+			}
+		}
+		Ok(response)
+	}
+
+	async fn handle_request(
+		&self,
+		request: &mut http::Request<hyper::Body>,
+	) -> Result<Option<http::Response<hyper::Body>>> {
+		if self.rejected_methods.contains(request.method()) {
+			return Ok(Some(self.method_not_allowed()));
+		}
+		if !self.is_authorized(request) {
+			return Ok(Some(unauthorized()));
+		}
+		#[cfg(debug_assertions)]
+		if request.uri().path() == echo::ECHO_PATH {
+			return echo::echo(request).await.map(Some);
+		}
+		let method = request.method().clone();
+		let request_headers = request.headers().clone();
+		let is_root = request.uri().path() == "/";
+		if let (true, RootPath::Redirect(location)) = (is_root, &self.root) {
+			let response = http::Response::builder()
+				.status(http::StatusCode::FOUND)
+				.header(http::header::LOCATION, location)
+				.body(hyper::Body::empty())?;
+			return Ok(Some(response));
+		}
+		if let Some(redirect) = self
+			.redirects
+			.iter()
+			.find(|redirect| redirect.from == request.uri().path())
+		{
+			let status = if redirect.permanent {
+				http::StatusCode::MOVED_PERMANENTLY
+			} else {
+				http::StatusCode::FOUND
+			};
+			let response = http::Response::builder()
+				.status(status)
+				.header(http::header::LOCATION, &redirect.to)
+				.body(hyper::Body::empty())?;
+			return Ok(Some(response));
+		}
+		let response = if is_root && self.root == RootPath::Index {
+			None
+		} else {
+			self.serve_page(request).await?
+		};
 		let response = match response {
 			Some(response) => Some(response),
 			None => self.serve_asset(request).await?,
 		};
+		let response = match response {
+			Some(response) => Some(response),
+			None => self.serve_spa_fallback(request),
+		};
+		let response = match response {
+			Some(response) => {
+				let response = conditional::apply_conditional(&method, &request_headers, response);
+				Some(range::apply_range(&method, &request_headers, response).await?)
+			}
+			None => None,
+		};
 		Ok(response)
 	}
 
@@ -186,7 +917,42 @@ impl Sunfish {
 		&self,
 		request: &mut http::Request<hyper::Body>,
 	) -> Result<Option<http::Response<hyper::Body>>> {
-		self.routes_handler.as_ref()(request).await
+		let mut response = match self.routes_handler.as_ref()(request).await? {
+			Some(response) => response,
+			None => return Ok(None),
+		};
+		if let Some(CanonicalPath(canonical_path)) = response.extensions().get::<CanonicalPath>() {
+			if let Some(canonical_url) = self.url(canonical_path) {
+				let link = format!("<{}>; rel=\"canonical\"", canonical_url);
+				if let Ok(link) = http::HeaderValue::from_str(&link) {
+					response.headers_mut().append(http::header::LINK, link);
+				}
+			}
+		}
+		if let Some(default_language) = &self.default_language {
+			// Static routes do not set a content type, so a response without one is HTML.
+			let is_html = response
+				.headers()
+				.get(http::header::CONTENT_TYPE)
+				.and_then(|content_type| content_type.to_str().ok())
+				.map_or(true, |content_type| content_type.starts_with("text/html"));
+			let headers = response.headers_mut();
+			if is_html && !headers.contains_key(http::header::CONTENT_LANGUAGE) {
+				if let Ok(default_language) = http::HeaderValue::from_str(default_language) {
+					headers.insert(http::header::CONTENT_LANGUAGE, default_language);
+				}
+			}
+		}
+		let response = self
+			.apply_cache_rules(request.uri().path(), "text/html", response)
+			.await?;
+		Ok(Some(response))
+	}
+
+	/// Get the absolute URL for `path` using the configured base URL.
+	pub fn url(&self, path: &str) -> Option<String> {
+		let base_url = self.base_url.as_deref()?;
+		Some(format!("{}{}", base_url.trim_end_matches('/'), path))
 	}
 
 	async fn serve_asset(
@@ -197,55 +963,1058 @@ impl Sunfish {
 		let uri = request.uri().clone();
 		let path_and_query = uri.path_and_query().unwrap();
 		let path = path_and_query.path();
-		if method != ::http::Method::GET {
-			return Ok(None);
+		// Decode the path and reject anything that could escape the output directory.
+		let path = match percent_decode_path(path) {
+			Some(path) => path,
+			None => return Ok(Some(bad_request())),
+		};
+		let path = Path::new(path.strip_prefix('/').unwrap_or(&path));
+		let is_safe = !path.to_str().unwrap().contains('\0')
+			&& path
+				.components()
+				.all(|component| matches!(component, std::path::Component::Normal(_)));
+		if !is_safe {
+			return Ok(Some(bad_request()));
 		}
-		let path = Path::new(path.strip_prefix('/').unwrap());
-		let file = if let Some(file) = self.output.read(path) {
-			file
+		// Resolve content addressed paths to the path of the file with the requested hash.
+		let cas_path = match path.strip_prefix("cas") {
+			Ok(full_hash) if self.content_addressed_assets => {
+				let full_hash = full_hash.to_str().unwrap();
+				match self.find_by_full_hash(full_hash) {
+					Some(cas_path) => Some(cas_path),
+					None => return Ok(None),
+				}
+			}
+			_ => None,
+		};
+		let is_content_addressed = cas_path.is_some();
+		let path = cas_path.as_deref().unwrap_or(path);
+		// Serve index.html for the root path if configured to.
+		let path = if path.as_os_str().is_empty() {
+			match self.root {
+				RootPath::RouteOrIndex | RootPath::Index => Path::new("index.html"),
+				RootPath::Route | RootPath::Redirect(_) => return Ok(None),
+			}
 		} else {
-			return Ok(None);
+			path
+		};
+		let is_read = method == http::Method::GET || method == http::Method::HEAD;
+		let is_overlaid = self.is_overlaid(path);
+		let file = match self.read_output(path) {
+			Ok(Some(file)) => file,
+			Ok(None) if !is_read => return Ok(None),
+			Ok(None) => return Ok(self.missing_asset_dir_response()),
+			Err(error) => return Ok(Some(asset_read_error(path, error))),
 		};
+		// Assets can only be read, so tell clients which methods they support.
+		if !is_read {
+			let response = http::Response::builder()
+				.status(http::StatusCode::METHOD_NOT_ALLOWED)
+				.header(http::header::ALLOW, "GET, HEAD")
+				.body(hyper::Body::from("method not allowed"))?;
+			return Ok(Some(response));
+		}
+		// Read the contents at most once, and only if they are needed, since files on disk may be large or fail to be read.
+		let data = OnceCell::new();
+		let read_data = || data.get_or_try_init(|| file.data());
 		let mut response = http::Response::builder();
-		if let Some(content_type) = content_type(path) {
+		if let Some(modified) = file.modified() {
+			response = response.header(
+				http::header::LAST_MODIFIED,
+				httpdate::fmt_http_date(modified),
+			);
+		}
+		if let Some(content_type) = self.content_type(path) {
 			response = response.header(http::header::CONTENT_TYPE, content_type);
+		} else if self.content_sniffing && path.extension().is_none() {
+			// Read only as much of the file as sniffing looks at, plus a byte to tell whether the file continues.
+			let prefix = match file.prefix(SNIFF_LEN + 1) {
+				Ok(prefix) => prefix,
+				Err(error) => return Ok(Some(asset_read_error(path, error))),
+			};
+			if let Some(content_type) = sniff_content_type(&prefix) {
+				response = response
+					.header(http::header::CONTENT_TYPE, content_type)
+					.header(http::header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+			}
 		}
-		if let Some(hash) = file.hash() {
-			response = response.header(http::header::ETAG, hash);
-			if let Some(etag) = request.headers().get(http::header::IF_NONE_MATCH) {
-				if etag.as_bytes() == hash.as_bytes() {
-					response = response.status(http::StatusCode::NOT_MODIFIED);
-					let response = response.body(hyper::Body::empty()).unwrap();
-					return Ok(Some(response));
+		let etag = match (self.full_hash_etags, file.hash(), file.full_hash()) {
+			(false, Some(hash), _) | (true, _, Some(hash)) => Some(Cow::Borrowed(hash)),
+			// Overlaid files are read from disk, so hash their contents.
+			(full_hash_etags, _, _) if is_overlaid => {
+				let data = match read_data() {
+					Ok(data) => data,
+					Err(error) => return Ok(Some(asset_read_error(path, error))),
+				};
+				if full_hash_etags {
+					Some(Cow::Owned(full_hash(data)))
+				} else {
+					Some(Cow::Owned(hash(data)))
 				}
 			}
+			_ => None,
+		};
+		if let Some(etag) = etag {
+			response = response.header(http::header::ETAG, etag.as_ref());
+		}
+		// Only URLs derived from the file's contents can be cached forever. Path based names keep their URL when the contents change, so they are revalidated like other responses.
+		let is_content_hashed = self.asset_manifest().map_or(false, |asset_manifest| {
+			asset_manifest.content_hashes || asset_manifest.is_bundle_path(path)
+		}) && is_hashed_asset_path(path)
+			&& !is_overlaid;
+		if is_content_addressed || is_content_hashed {
+			response = response.header(
+				http::header::CACHE_CONTROL,
+				"public, max-age=31536000, immutable",
+			);
+		}
+		// Send the gzipped variant if there is one and the client accepts it. It is either embedded with the file or a sibling file with a .gz extension in the same layer as the file. Overlaid files without a sibling of their own are compressed on the fly. Brotli is not supported.
+		let gzip = match file.gzip() {
+			Some(gzip) => Some(Cow::Borrowed(gzip)),
+			None => self.read_gzip_sibling(path, is_overlaid),
+		};
+		let gzip_on_the_fly = gzip.is_none()
+			&& is_overlaid
+			&& is_compressible(path)
+			&& file
+				.size()
+				.map_or(false, |size| size <= STREAMING_THRESHOLD);
+		if gzip.is_some() || gzip_on_the_fly {
+			response = response.header(http::header::VARY, "Accept-Encoding");
+		}
+		let gzip = match gzip {
+			_ if !accepts_gzip(request.headers()) => None,
+			None if gzip_on_the_fly => {
+				let data = match read_data() {
+					Ok(data) => data,
+					Err(error) => return Ok(Some(asset_read_error(path, error))),
+				};
+				Some(Cow::Owned(gzip_bytes(data)?))
+			}
+			gzip => gzip,
+		};
+		if gzip.is_some() {
+			response = response.header(http::header::CONTENT_ENCODING, "gzip");
 		}
+		// The representation includes its content coding, so the digests of a gzipped response are of the gzipped bytes.
+		if let (true, Some(digest_name)) = (self.digest_headers, digest_name()) {
+			let full_hash = match (&gzip, file.full_hash()) {
+				(Some(gzip), _) => Cow::Owned(full_hash(gzip)),
+				(None, Some(full_hash)) => Cow::Borrowed(full_hash),
+				(None, None) => match read_data() {
+					Ok(data) => Cow::Owned(full_hash(data)),
+					Err(error) => return Ok(Some(asset_read_error(path, error))),
+				},
+			};
+			let digest = base64_encode(&hex::decode(full_hash.as_ref())?);
+			response = response
+				.header("repr-digest", format!("{}=:{}:", digest_name, digest))
+				.header("digest", format!("{}={}", digest_name, digest));
+		}
+		// Stream large files instead of holding their whole contents in the body.
+		let (len, body) = match gzip {
+			Some(gzip) => (gzip.len() as u64, hyper::Body::from(gzip)),
+			None => {
+				let size = match file.size() {
+					Ok(size) => size,
+					Err(error) => return Ok(Some(asset_read_error(path, error))),
+				};
+				if size > STREAMING_THRESHOLD {
+					(size, hyper::Body::wrap_stream(file.stream()))
+				} else {
+					let data = match data.into_inner().map(Ok).unwrap_or_else(|| file.data()) {
+						Ok(data) => data,
+						Err(error) => return Ok(Some(asset_read_error(path, error))),
+					};
+					(data.len() as u64, hyper::Body::from(data))
+				}
+			}
+		};
+		// Set the length explicitly so that empty and streamed files are sent with a Content-Length.
+		response = response.header(http::header::CONTENT_LENGTH, len);
 		response = response.status(http::StatusCode::OK);
-		let response = response.body(hyper::Body::from(file.data())).unwrap();
+		let response = response.body(body).unwrap();
+		let mut response = self
+			.apply_cache_rules(uri.path(), "application/octet-stream", response)
+			.await?;
+		// Files at hashed paths that are not derived from their contents keep their URL across builds, so make clients revalidate them unless a cache rule says otherwise.
+		if is_hashed_asset_path(path)
+			&& !response.headers().contains_key(http::header::CACHE_CONTROL)
+		{
+			response.headers_mut().insert(
+				http::header::CACHE_CONTROL,
+				http::HeaderValue::from_static("no-cache, must-revalidate"),
+			);
+		}
+		// Respond to HEAD requests with the same headers and no body.
+		let response = if method == http::Method::HEAD {
+			let (parts, _) = response.into_parts();
+			http::Response::from_parts(parts, hyper::Body::empty())
+		} else {
+			response
+		};
 		Ok(Some(response))
 	}
+
+	fn serve_spa_fallback(
+		&self,
+		request: &http::Request<hyper::Body>,
+	) -> Option<http::Response<hyper::Body>> {
+		let spa_fallback = self.spa_fallback.as_deref()?;
+		if request.method() != http::Method::GET && request.method() != http::Method::HEAD {
+			return None;
+		}
+		// Requests for paths with an extension are for missing files, not client routes.
+		if Path::new(request.uri().path()).extension().is_some() {
+			return None;
+		}
+		let file = match self.read_output(spa_fallback) {
+			Ok(file) => file?,
+			Err(error) => {
+				tracing::error!(?error, path = %spa_fallback.display(), "failed to read the SPA fallback");
+				return Some(internal_server_error());
+			}
+		};
+		let data = match file.data() {
+			Ok(data) => data,
+			Err(error) => {
+				tracing::error!(?error, path = %spa_fallback.display(), "failed to read the SPA fallback");
+				return Some(internal_server_error());
+			}
+		};
+		// Files in an Fs directory have no stored hash, so hash the contents.
+		let etag = match (self.full_hash_etags, file.hash(), file.full_hash()) {
+			(false, Some(hash), _) | (true, _, Some(hash)) => format!("\"{}\"", hash),
+			(false, None, _) => format!("\"{}\"", hash(&data)),
+			(true, _, None) => format!("\"{}\"", full_hash(&data)),
+		};
+		let response = http::Response::builder()
+			.status(http::StatusCode::OK)
+			.header(
+				http::header::CONTENT_TYPE,
+				self.content_type(spa_fallback)
+					.unwrap_or("text/html; charset=utf-8"),
+			)
+			.header(http::header::ETAG, etag)
+			.header(http::header::CACHE_CONTROL, "no-cache")
+			.body(hyper::Body::from(data))
+			.unwrap();
+		Some(response)
+	}
+
+	/// Apply the first cache rule that matches `path` and the response's content type, or `default_content_type` if it has none. Responses that already have a `Cache-Control` header are left as they are.
+	async fn apply_cache_rules(
+		&self,
+		path: &str,
+		default_content_type: &str,
+		response: http::Response<hyper::Body>,
+	) -> Result<http::Response<hyper::Body>> {
+		if response.status() != http::StatusCode::OK
+			|| response.headers().contains_key(http::header::CACHE_CONTROL)
+		{
+			return Ok(response);
+		}
+		let content_type = response
+			.headers()
+			.get(http::header::CONTENT_TYPE)
+			.and_then(|content_type| content_type.to_str().ok())
+			.unwrap_or(default_content_type);
+		let content_type = content_type.split(';').next().unwrap().trim();
+		let cache_policy = self
+			.cache_rules
+			.iter()
+			.find(|(cache_rule, _)| match cache_rule {
+				CacheRule::PathPrefix(prefix) => path.starts_with(prefix.as_str()),
+				CacheRule::ContentType(rule_content_type) => {
+					content_type.eq_ignore_ascii_case(rule_content_type)
+				}
+			})
+			.map(|(_, cache_policy)| *cache_policy);
+		match cache_policy {
+			Some(cache_policy) => apply_cache_policy(cache_policy, response).await,
+			None => Ok(response),
+		}
+	}
+
+	/// Whether the request carries the bearer token, if one is required for its path.
+	fn is_authorized(&self, request: &http::Request<hyper::Body>) -> bool {
+		let bearer_token = match &self.bearer_token {
+			Some(bearer_token) => bearer_token,
+			None => return true,
+		};
+		let path = request.uri().path();
+		if self
+			.bearer_token_allowlist
+			.iter()
+			.any(|allowed_path| allowed_path == path)
+		{
+			return true;
+		}
+		let token = request
+			.headers()
+			.get(http::header::AUTHORIZATION)
+			.and_then(|authorization| authorization.to_str().ok())
+			.and_then(|authorization| authorization.split_once(' '))
+			.filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+			.map(|(_, token)| token.trim());
+		match token {
+			Some(token) => constant_time_eq(token.as_bytes(), bearer_token.as_bytes()),
+			None => false,
+		}
+	}
+
+	fn method_not_allowed(&self) -> http::Response<hyper::Body> {
+		let allow = [
+			http::Method::GET,
+			http::Method::HEAD,
+			http::Method::POST,
+			http::Method::PUT,
+			http::Method::PATCH,
+			http::Method::DELETE,
+			http::Method::OPTIONS,
+		]
+		.iter()
+		.filter(|method| !self.rejected_methods.contains(method))
+		.map(|method| method.as_str())
+		.collect::<Vec<_>>()
+		.join(", ");
+		http::Response::builder()
+			.status(http::StatusCode::METHOD_NOT_ALLOWED)
+			.header(http::header::ALLOW, allow)
+			.body(hyper::Body::from("method not allowed"))
+			.unwrap()
+	}
+
+	/// Get the content type for `path`, consulting `content_types` before the built in table.
+	fn content_type(&self, path: &Path) -> Option<&str> {
+		let extension = path
+			.extension()
+			.and_then(|extension| extension.to_str())
+			.map(|extension| extension.to_ascii_lowercase());
+		let custom_content_type = extension
+			.and_then(|extension| self.content_types.get(&extension))
+			.map(String::as_str);
+		custom_content_type.or_else(|| content_type(path))
+	}
+
+	/// Read the file at `path` from the overlay if it has one, and otherwise from the output.
+	fn read_output(&self, path: &Path) -> std::io::Result<Option<FsOrIncludedFile>> {
+		if let Some(overlay) = &self.overlay {
+			if let Some(file) = overlay.read(path)? {
+				return Ok(Some(file));
+			}
+		}
+		self.output.read(path)
+	}
+
+	/// Read the gzipped sibling of the file at `path`, if the builder wrote one. The sibling is read from the overlay if the file is overlaid, and otherwise from the output, so that it always has the same contents as the file.
+	fn read_gzip_sibling(&self, path: &Path, is_overlaid: bool) -> Option<Cow<'static, [u8]>> {
+		let extension = path.extension()?.to_str()?;
+		if extension.eq_ignore_ascii_case("gz") {
+			return None;
+		}
+		let gzip_path = path.with_extension(format!("{}.gz", extension));
+		let gzip = match (is_overlaid, &self.overlay) {
+			(true, Some(overlay)) => overlay.read(&gzip_path),
+			_ => self.output.read(&gzip_path),
+		};
+		match gzip.and_then(|gzip| gzip.map(|gzip| gzip.data()).transpose()) {
+			Ok(gzip) => gzip,
+			Err(error) => {
+				tracing::error!(?error, path = %gzip_path.display(), "failed to read asset");
+				None
+			}
+		}
+	}
+
+	/// Find the path of the file whose contents have the full hash `full_hash`, as `read_output` reads it. Files in the overlay are found by hashing them, and files in the output that the overlay shadows are not found, so a content addressed path always serves the bytes it names.
+	fn find_by_full_hash(&self, full_hash: &str) -> Option<PathBuf> {
+		if let Some(path) = self
+			.overlay
+			.as_ref()
+			.and_then(|overlay| overlay.find_by_full_hash(full_hash))
+		{
+			return Some(path);
+		}
+		let path = match &self.cas_index {
+			Some(cas_index) => cas_index.get(full_hash).cloned(),
+			None => self.output.find_by_full_hash(full_hash),
+		}?;
+		if self.is_overlaid(&path) {
+			return None;
+		}
+		Some(path)
+	}
+
+	fn is_overlaid(&self, path: &Path) -> bool {
+		self.overlay.as_ref().map_or(false, |overlay| {
+			overlay.is_included(path) && overlay.0.join(path).is_file()
+		})
+	}
+
+	/// If diagnostics are enabled and the output is an `Fs` directory that does not exist, explain that in a `404`.
+	fn missing_asset_dir_response(&self) -> Option<http::Response<hyper::Body>> {
+		if !self.asset_dir_diagnostics {
+			return None;
+		}
+		let fs_directory = match &self.output {
+			IncludeDir::Fs(fs_directory) if !fs_directory.root_exists() => fs_directory,
+			_ => return None,
+		};
+		let message = format!(
+			"asset dir {} not found — did the project move?",
+			fs_directory.0.display()
+		);
+		let response = http::Response::builder()
+			.status(http::StatusCode::NOT_FOUND)
+			.header(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+			.body(hyper::Body::from(message))
+			.unwrap();
+		Some(response)
+	}
 }
 
-fn content_type(path: &std::path::Path) -> Option<&'static str> {
-	let path = path.to_str().unwrap();
-	if path.ends_with(".css") {
-		Some("text/css")
-	} else if path.ends_with(".js") {
-		Some("text/javascript")
-	} else if path.ends_with(".svg") {
-		Some("image/svg+xml")
-	} else if path.ends_with(".wasm") {
-		Some("application/wasm")
+fn default_rejected_methods() -> Vec<http::Method> {
+	vec![
+		http::Method::TRACE,
+		http::Method::from_bytes(b"TRACK").unwrap(),
+	]
+}
+
+fn unauthorized() -> http::Response<hyper::Body> {
+	http::Response::builder()
+		.status(http::StatusCode::UNAUTHORIZED)
+		.header(http::header::WWW_AUTHENTICATE, "Bearer")
+		.header(http::header::CACHE_CONTROL, "no-store")
+		.body(hyper::Body::from("unauthorized"))
+		.unwrap()
+}
+
+/// Compare two byte strings in time that depends only on their lengths, so the comparison does not reveal how much of a secret matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Assets larger than this many bytes are streamed rather than sent from a single buffer.
+const STREAMING_THRESHOLD: u64 = 1024 * 1024;
+
+/// Log a failure to read the asset at `path` and respond with a `500`.
+fn asset_read_error(path: &Path, error: std::io::Error) -> http::Response<hyper::Body> {
+	tracing::error!(?error, path = %path.display(), "failed to read asset");
+	internal_server_error()
+}
+
+fn internal_server_error() -> http::Response<hyper::Body> {
+	http::Response::builder()
+		.status(http::StatusCode::INTERNAL_SERVER_ERROR)
+		.body(hyper::Body::from("internal server error"))
+		.unwrap()
+}
+
+fn bad_request() -> http::Response<hyper::Body> {
+	http::Response::builder()
+		.status(http::StatusCode::BAD_REQUEST)
+		.body(hyper::Body::from("bad request"))
+		.unwrap()
+}
+
+/// Whether `path` is the path of an asset or client file named by a hash, as produced by `build`. With `BuildOptions::content_hashes`, the hash is of the file's contents, so the file at such a path never changes.
+fn is_hashed_asset_path(path: &Path) -> bool {
+	let mut components = path.components();
+	let directory = components
+		.next()
+		.and_then(|component| component.as_os_str().to_str());
+	let file_name = components
+		.next()
+		.and_then(|component| component.as_os_str().to_str());
+	let (directory, file_name) = match (directory, file_name, components.next()) {
+		(Some(directory), Some(file_name), None) => (directory, file_name),
+		_ => return false,
+	};
+	if directory != "assets" && directory != "js" {
+		return false;
+	}
+	// The hash is followed by the extension, or by _bg for wasm-bindgen output.
+	let is_hash_prefix = file_name.get(..16).map_or(false, |hash| {
+		hash.bytes()
+			.all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+	});
+	is_hash_prefix && matches!(file_name.as_bytes().get(16), None | Some(b'.') | Some(b'_'))
+}
+
+/// Whether the file at `path` is worth compressing, judging by its extension.
+pub(crate) fn is_compressible(path: &Path) -> bool {
+	let extension = match path.extension().and_then(|extension| extension.to_str()) {
+		Some(extension) => extension.to_ascii_lowercase(),
+		None => return false,
+	};
+	matches!(extension.as_str(), "css" | "html" | "js" | "svg" | "wasm")
+}
+
+fn gzip_bytes(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+	use std::io::Write;
+	let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+	encoder.write_all(bytes)?;
+	encoder.finish()
+}
+
+/// Whether the request's `Accept-Encoding` header accepts gzip with a nonzero quality.
+fn accepts_gzip(request_headers: &http::HeaderMap) -> bool {
+	request_headers
+		.get_all(http::header::ACCEPT_ENCODING)
+		.iter()
+		.filter_map(|accept_encoding| accept_encoding.to_str().ok())
+		.flat_map(|accept_encoding| accept_encoding.split(','))
+		.any(|coding| {
+			let mut parameters = coding.split(';').map(|parameter| parameter.trim());
+			let name = parameters.next().unwrap_or("");
+			let is_gzip = name.eq_ignore_ascii_case("gzip")
+				|| name.eq_ignore_ascii_case("x-gzip")
+				|| name == "*";
+			let is_refused = parameters.any(|parameter| {
+				parameter
+					.strip_prefix("q=")
+					.and_then(|quality| quality.parse::<f32>().ok())
+					== Some(0.0)
+			});
+			is_gzip && !is_refused
+		})
+}
+
+/// Guess the content type of `data` from its leading bytes.
+fn sniff_content_type(data: &[u8]) -> Option<&'static str> {
+	if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+		Some("image/png")
+	} else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+		Some("image/jpeg")
+	} else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+		Some("image/gif")
+	} else if data.starts_with(b"%PDF-") {
+		Some("application/pdf")
+	} else if is_text(data) {
+		Some("text/plain; charset=utf-8")
 	} else {
 		None
 	}
 }
 
+/// The number of leading bytes of a file `sniff_content_type` looks at.
+const SNIFF_LEN: usize = 512;
+
+/// Whether `data` is non-empty UTF-8 without control characters other than whitespace. Only the first `SNIFF_LEN` bytes are checked.
+fn is_text(data: &[u8]) -> bool {
+	if data.is_empty() {
+		return false;
+	}
+	let prefix = &data[..data.len().min(SNIFF_LEN)];
+	let text = match std::str::from_utf8(prefix) {
+		Ok(text) => text,
+		// The prefix may end in the middle of a character.
+		Err(error) if error.error_len().is_none() && prefix.len() < data.len() => {
+			std::str::from_utf8(&prefix[..error.valid_up_to()]).unwrap()
+		}
+		Err(_) => return false,
+	};
+	text.chars()
+		.all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+/// Get the content type for `path` by its extension, ignoring case, or `None` if the extension is unknown.
+fn content_type(path: &std::path::Path) -> Option<&'static str> {
+	let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+	let content_type = match extension.as_str() {
+		"apng" => "image/apng",
+		"avif" => "image/avif",
+		"bmp" => "image/bmp",
+		"css" => "text/css",
+		"csv" => "text/csv",
+		"gif" => "image/gif",
+		"flac" => "audio/flac",
+		"gz" => "application/gzip",
+		"htm" | "html" => "text/html; charset=utf-8",
+		"ico" => "image/x-icon",
+		"ics" => "text/calendar",
+		"jpeg" | "jpg" => "image/jpeg",
+		"js" | "mjs" => "text/javascript",
+		"json" | "map" => "application/json",
+		"jsonld" => "application/ld+json",
+		"m4a" => "audio/mp4",
+		"md" => "text/markdown",
+		"mp3" => "audio/mpeg",
+		"mov" => "video/quicktime",
+		"mp4" => "video/mp4",
+		"oga" | "ogg" | "opus" => "audio/ogg",
+		"ogv" => "video/ogg",
+		"otf" => "font/otf",
+		"pdf" => "application/pdf",
+		"png" => "image/png",
+		"rss" => "application/rss+xml",
+		"svg" => "image/svg+xml",
+		"tif" | "tiff" => "image/tiff",
+		"tsv" => "text/tab-separated-values",
+		"ttf" => "font/ttf",
+		"txt" => "text/plain",
+		"wasm" => "application/wasm",
+		"wav" => "audio/wav",
+		"weba" => "audio/webm",
+		"webm" => "video/webm",
+		"webmanifest" => "application/manifest+json",
+		"webp" => "image/webp",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		"xml" => "application/xml",
+		"yaml" | "yml" => "application/yaml",
+		"zip" => "application/zip",
+		_ => return None,
+	};
+	Some(content_type)
+}
+
+/// The name of `HASH_ALGORITHM` in the HTTP digest algorithm registry, if it has one.
+fn digest_name() -> Option<&'static str> {
+	match HASH_ALGORITHM {
+		"sha256" => Some("sha-256"),
+		"sha512" => Some("sha-512"),
+		_ => None,
+	}
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+	const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+	for chunk in bytes.chunks(3) {
+		let b = [
+			chunk[0],
+			chunk.get(1).copied().unwrap_or(0),
+			chunk.get(2).copied().unwrap_or(0),
+		];
+		let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+		for i in 0..4 {
+			if i <= chunk.len() {
+				encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+			} else {
+				encoded.push('=');
+			}
+		}
+	}
+	encoded
+}
+
+/// The algorithm used by `hash`, selected when compiling sunfish with the `SUNFISH_HASH_ALGORITHM` environment variable. One of `sha224`, `sha256`, `sha384`, or `sha512`, and sunfish fails to compile if it is anything else. Defaults to `sha256`. The `include_dir!` macro reads the same variable, and the directories it produces panic on creation if the two disagree.
+pub const HASH_ALGORITHM: &str = match option_env!("SUNFISH_HASH_ALGORITHM") {
+	Some(algorithm) => algorithm,
+	None => "sha256",
+};
+
+// Fail to compile, instead of panicking on the first request, if `SUNFISH_HASH_ALGORITHM` names an algorithm `full_hash` does not support.
+const _: () = assert!(
+	is_supported_hash_algorithm(HASH_ALGORITHM),
+	"unsupported hash algorithm in SUNFISH_HASH_ALGORITHM, expected one of sha224, sha256, sha384, or sha512",
+);
+
+const fn is_supported_hash_algorithm(algorithm: &str) -> bool {
+	const SUPPORTED: [&str; 4] = ["sha224", "sha256", "sha384", "sha512"];
+	let mut i = 0;
+	while i < SUPPORTED.len() {
+		if const_str_eq(SUPPORTED[i], algorithm) {
+			return true;
+		}
+		i += 1;
+	}
+	false
+}
+
+/// Compare two strings in a const context, where `==` is not available.
+const fn const_str_eq(a: &str, b: &str) -> bool {
+	let (a, b) = (a.as_bytes(), b.as_bytes());
+	if a.len() != b.len() {
+		return false;
+	}
+	let mut i = 0;
+	while i < a.len() {
+		if a[i] != b[i] {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
+
 pub fn hash(bytes: impl AsRef<[u8]>) -> String {
-	let mut hash: sha2::Sha256 = Digest::new();
-	hash.update(bytes);
-	let hash = hash.finalize();
-	let hash = hex::encode(hash);
+	let hash = full_hash(bytes);
 	let hash = &hash[0..16];
 	hash.to_owned()
 }
+
+/// Hash `bytes` with `HASH_ALGORITHM`, returning the full hex-encoded digest.
+pub fn full_hash(bytes: impl AsRef<[u8]>) -> String {
+	match HASH_ALGORITHM {
+		"sha224" => hex::encode(sha2::Sha224::digest(bytes)),
+		"sha256" => hex::encode(sha2::Sha256::digest(bytes)),
+		"sha384" => hex::encode(sha2::Sha384::digest(bytes)),
+		"sha512" => hex::encode(sha2::Sha512::digest(bytes)),
+		_ => unreachable!("HASH_ALGORITHM is checked when compiling"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Read, Write};
+
+	fn sunfish(output_path: &Path) -> Sunfish {
+		Sunfish::new(
+			IncludeDir::Fs(FsDirectory(output_path.to_owned(), PathFilter::default())),
+			Box::new(|_| Box::pin(async { Ok(None) })),
+			Vec::new(),
+		)
+	}
+
+	async fn get(
+		sunfish: &Sunfish,
+		path: &str,
+		headers: &[(&str, &str)],
+	) -> http::Response<hyper::Body> {
+		let mut request = http::Request::builder().uri(path);
+		for (name, value) in headers {
+			request = request.header(*name, *value);
+		}
+		let mut request = request.body(hyper::Body::empty()).unwrap();
+		sunfish.handle(&mut request).await.unwrap().unwrap()
+	}
+
+	async fn body(response: http::Response<hyper::Body>) -> Vec<u8> {
+		hyper::body::to_bytes(response.into_body())
+			.await
+			.unwrap()
+			.to_vec()
+	}
+
+	fn gunzip(bytes: &[u8]) -> Vec<u8> {
+		let mut data = Vec::new();
+		flate2::read::GzDecoder::new(bytes)
+			.read_to_end(&mut data)
+			.unwrap();
+		data
+	}
+
+	#[tokio::test]
+	async fn overlaid_files_are_not_sent_with_the_outputs_gzipped_sibling() {
+		let output_dir = tempfile::tempdir().unwrap();
+		let overlay_dir = tempfile::tempdir().unwrap();
+		std::fs::write(output_dir.path().join("app.css"), "old").unwrap();
+		let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+		encoder.write_all(b"old").unwrap();
+		std::fs::write(
+			output_dir.path().join("app.css.gz"),
+			encoder.finish().unwrap(),
+		)
+		.unwrap();
+		std::fs::write(overlay_dir.path().join("app.css"), "new").unwrap();
+		let sunfish = sunfish(output_dir.path()).with_overlay(overlay_dir.path());
+		let response = get(&sunfish, "/app.css", &[("accept-encoding", "gzip")]).await;
+		assert_eq!(response.headers()[http::header::CONTENT_ENCODING], "gzip");
+		assert_eq!(gunzip(&body(response).await), b"new");
+		let response = get(&sunfish, "/app.css", &[]).await;
+		assert!(!response
+			.headers()
+			.contains_key(http::header::CONTENT_ENCODING));
+		assert_eq!(body(response).await, b"new");
+	}
+
+	#[tokio::test]
+	async fn digests_are_of_the_bytes_sent() {
+		let output_dir = tempfile::tempdir().unwrap();
+		std::fs::write(output_dir.path().join("app.css"), "body { color: red; }").unwrap();
+		let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+		encoder.write_all(b"body { color: red; }").unwrap();
+		std::fs::write(
+			output_dir.path().join("app.css.gz"),
+			encoder.finish().unwrap(),
+		)
+		.unwrap();
+		let sunfish = sunfish(output_dir.path()).with_digest_headers();
+		let digest_name = match digest_name() {
+			Some(digest_name) => digest_name,
+			None => return,
+		};
+		for headers in [&[("accept-encoding", "gzip")][..], &[]] {
+			let response = get(&sunfish, "/app.css", headers).await;
+			let repr_digest = response.headers()["repr-digest"].clone();
+			let digest = response.headers()["digest"].clone();
+			let body = body(response).await;
+			let expected = base64_encode(&hex::decode(full_hash(&body)).unwrap());
+			assert_eq!(
+				repr_digest,
+				format!("{}=:{}:", digest_name, expected).as_str()
+			);
+			assert_eq!(digest, format!("{}={}", digest_name, expected).as_str());
+		}
+	}
+
+	#[tokio::test]
+	async fn static_pages_are_html() {
+		let route = Route::new_static(|_| "<p>hello</p>".to_owned());
+		let mut request = http::Request::builder()
+			.uri("/")
+			.body(hyper::Body::empty())
+			.unwrap();
+		let response = route.handle(&mut request).await.unwrap();
+		assert_eq!(
+			response.headers()[http::header::CONTENT_TYPE],
+			"text/html; charset=utf-8"
+		);
+	}
+
+	#[tokio::test]
+	async fn content_addressed_paths_respect_the_overlay() {
+		let output_dir = tempfile::tempdir().unwrap();
+		let overlay_dir = tempfile::tempdir().unwrap();
+		std::fs::write(output_dir.path().join("app.css"), "old").unwrap();
+		std::fs::write(overlay_dir.path().join("app.css"), "new").unwrap();
+		let sunfish = sunfish(output_dir.path())
+			.with_overlay(overlay_dir.path())
+			.with_content_addressed_assets();
+		let mut request = http::Request::builder()
+			.uri(format!("/cas/{}", full_hash("old")))
+			.body(hyper::Body::empty())
+			.unwrap();
+		assert!(sunfish.handle(&mut request).await.unwrap().is_none());
+		let response = get(&sunfish, &format!("/cas/{}", full_hash("new")), &[]).await;
+		assert_eq!(body(response).await, b"new");
+	}
+
+	#[test]
+	fn percent_decode_path_table() {
+		let cases = [
+			("/my%20file.css", Some("/my file.css")),
+			("/caf%C3%A9.css", Some("/café.css")),
+			("/%2e%2e/secret", Some("/../secret")),
+			("/a%2Fb", Some("/a%2Fb")),
+			("/a%2fb", Some("/a%2fb")),
+			("/%zz", None),
+			("/%2", None),
+			("/%", None),
+			("/%FF", None),
+		];
+		for (path, expected) in cases {
+			assert_eq!(percent_decode_path(path).as_deref(), expected, "{}", path);
+		}
+	}
+
+	#[tokio::test]
+	async fn unsafe_asset_paths_are_bad_requests() {
+		let output_dir = tempfile::tempdir().unwrap();
+		let sunfish = sunfish(output_dir.path());
+		for path in [
+			"/%2e%2e/secret",
+			"/a/%2E%2E/%2e%2e/secret",
+			"/%zz",
+			"/a%00b",
+			"/./a",
+		] {
+			let response = get(&sunfish, path, &[]).await;
+			assert_eq!(response.status(), http::StatusCode::BAD_REQUEST, "{}", path);
+		}
+	}
+
+	#[test]
+	fn percent_decode_path_never_panics() {
+		// Decode many pseudorandom paths built from bytes that are likely to form escapes.
+		let alphabet = b"%/.2eEfF0aZz\x00\xc3\xa9 ";
+		let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+		let mut next = move || {
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+			state as usize
+		};
+		for _ in 0..10_000 {
+			let len = next() % 16;
+			let path = (0..len)
+				.map(|_| alphabet[next() % alphabet.len()])
+				.collect::<Vec<_>>();
+			let path = String::from_utf8_lossy(&path);
+			if let Some(decoded) = percent_decode_path(&path) {
+				// Encoded slashes are never decoded into separators.
+				assert_eq!(decoded.matches('/').count(), path.matches('/').count());
+			}
+		}
+	}
+
+	#[test]
+	fn content_types_are_looked_up_by_extension_ignoring_case() {
+		for (path, expected) in [
+			("fonts/inter.woff2", Some("font/woff2")),
+			("fonts/inter.WOFF", Some("font/woff")),
+			("fonts/inter.ttf", Some("font/ttf")),
+			("data.json", Some("application/json")),
+			("DATA.JSON", Some("application/json")),
+			("app.js.map", Some("application/json")),
+			("Index.HTML", Some("text/html; charset=utf-8")),
+			("unknown.xyz", None),
+			("README", None),
+		] {
+			assert_eq!(content_type(Path::new(path)), expected, "{}", path);
+		}
+	}
+
+	#[tokio::test]
+	async fn custom_content_types_are_registered_by_extension() {
+		let output_dir = tempfile::tempdir().unwrap();
+		for file_name in ["model.glb", "MODEL2.GLB", "data.json", "inter.woff2"] {
+			std::fs::write(output_dir.path().join(file_name), "").unwrap();
+		}
+		let sunfish = sunfish(output_dir.path())
+			.with_content_type(".GLB", "model/gltf-binary")
+			.with_content_type("json", "application/vnd.api+json");
+		for (path, expected) in [
+			("/model.glb", "model/gltf-binary"),
+			("/MODEL2.GLB", "model/gltf-binary"),
+			("/data.json", "application/vnd.api+json"),
+			("/inter.woff2", "font/woff2"),
+		] {
+			let response = get(&sunfish, path, &[]).await;
+			assert_eq!(
+				response.headers()[http::header::CONTENT_TYPE],
+				expected,
+				"{}",
+				path
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn empty_files_are_served_with_a_zero_length() {
+		let file = include_dir::IncludedFile {
+			data: include_dir::IncludedData::Raw(b""),
+			hash: Box::leak(hash(b"").into_boxed_str()),
+			full_hash: Box::leak(full_hash(b"").into_boxed_str()),
+			gzip: None,
+			modified: 0,
+		};
+		let sunfish = Sunfish::new(
+			IncludeDir::Included(include_dir::IncludedDirectory(
+				[(Path::new("empty.css"), file)].into_iter().collect(),
+			)),
+			Box::new(|_| Box::pin(async { Ok(None) })),
+			Vec::new(),
+		);
+		let response = get(&sunfish, "/empty.css", &[("accept-encoding", "gzip")]).await;
+		assert_eq!(response.status(), http::StatusCode::OK);
+		assert_eq!(response.headers()[http::header::CONTENT_LENGTH], "0");
+		assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/css");
+		assert!(body(response).await.is_empty());
+	}
+
+	#[tokio::test]
+	async fn range_requests_are_served_through_handle() {
+		let output_dir = tempfile::tempdir().unwrap();
+		let data = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+		std::fs::write(output_dir.path().join("video.mp4"), &data).unwrap();
+		let sunfish = sunfish(output_dir.path());
+		let response = get(&sunfish, "/video.mp4", &[("range", "bytes=100-199")]).await;
+		assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			response.headers()[http::header::CONTENT_RANGE],
+			"bytes 100-199/1000"
+		);
+		assert_eq!(response.headers()[http::header::CONTENT_LENGTH], "100");
+		assert_eq!(body(response).await, &data[100..200]);
+		let response = get(&sunfish, "/video.mp4", &[("range", "bytes=500-")]).await;
+		assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			response.headers()[http::header::CONTENT_RANGE],
+			"bytes 500-999/1000"
+		);
+		assert_eq!(body(response).await, &data[500..]);
+		let response = get(&sunfish, "/video.mp4", &[("range", "bytes=1000-1999")]).await;
+		assert_eq!(response.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+		assert_eq!(
+			response.headers()[http::header::CONTENT_RANGE],
+			"bytes */1000"
+		);
+		assert!(body(response).await.is_empty());
+	}
+
+	#[tokio::test]
+	async fn route_cache_policies_only_apply_to_successful_responses() {
+		let route = Route::new_dynamic(|request| {
+			let status = match request.uri().path() {
+				"/missing" => http::StatusCode::NOT_FOUND,
+				_ => http::StatusCode::OK,
+			};
+			Box::pin(async move {
+				Ok(http::Response::builder()
+					.status(status)
+					.body(hyper::Body::empty())
+					.unwrap())
+			})
+		})
+		.with_cache_policy(CachePolicy::Immutable);
+		let mut request = http::Request::builder()
+			.uri("/found")
+			.body(hyper::Body::empty())
+			.unwrap();
+		let response = route.handle(&mut request).await.unwrap();
+		assert_eq!(
+			response.headers()[http::header::CACHE_CONTROL],
+			"public, max-age=31536000, immutable"
+		);
+		let mut request = http::Request::builder()
+			.uri("/missing")
+			.body(hyper::Body::empty())
+			.unwrap();
+		let response = route.handle(&mut request).await.unwrap();
+		assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+		assert!(!response.headers().contains_key(http::header::CACHE_CONTROL));
+	}
+
+	#[tokio::test]
+	async fn extensionless_assets_are_sniffed_from_their_first_bytes() {
+		let output_dir = tempfile::tempdir().unwrap();
+		let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+		png.resize(STREAMING_THRESHOLD as usize + 1, 0);
+		std::fs::write(output_dir.path().join("image"), &png).unwrap();
+		// A character that straddles the end of the sniffed prefix does not make text binary.
+		let text = format!("{}é and more text", "a".repeat(SNIFF_LEN - 1));
+		std::fs::write(output_dir.path().join("notes"), text).unwrap();
+		let sunfish = sunfish(output_dir.path()).with_content_sniffing();
+		for (path, expected) in [
+			("/image", "image/png"),
+			("/notes", "text/plain; charset=utf-8"),
+		] {
+			let response = get(&sunfish, path, &[]).await;
+			assert_eq!(
+				response.headers()[http::header::CONTENT_TYPE],
+				expected,
+				"{}",
+				path
+			);
+		}
+	}
+
+	#[test]
+	fn only_known_hash_algorithms_are_supported() {
+		for (algorithm, expected) in [
+			("sha224", true),
+			("sha256", true),
+			("sha384", true),
+			("sha512", true),
+			("sha1", false),
+			("SHA256", false),
+			("sha2566", false),
+			("", false),
+		] {
+			assert_eq!(
+				is_supported_hash_algorithm(algorithm),
+				expected,
+				"{}",
+				algorithm
+			);
+		}
+	}
+}
@@ -1,22 +1,193 @@
 use digest::Digest;
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use proc_macro2::Span;
 use quote::quote;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use syn::{
+	parse::{Parse, ParseStream},
+	Token,
+};
+
+/// The arguments to `include_dir!`.
+///
+/// ```ignore
+/// include_dir!("output", env = "ASSETS_DIR", features = { "lite" => "output-lite" }, compress = true, compressed_only = true, include = ["**/*.js", "**/*.css"], exclude = ["**/*.map", "**/.DS_Store"])
+/// ```
+///
+/// The directory is resolved in this order:
+/// 1. If `env` names an environment variable that is set at compile time, its value is used.
+/// 2. Otherwise, the first entry in `features` whose cargo feature is enabled is used.
+/// 3. Otherwise, the default path is used.
+///
+/// Cargo does not track environment variables read by proc macros, so changing the variable requires a rebuild, for example by emitting `cargo:rerun-if-env-changed` from a build script.
+///
+/// If `include` globs are given, only files matching one of them are included, and files matching any of the `exclude` globs are left out. The globs use gitignore syntax relative to the directory and apply the same way in debug builds, where the directory is read from the filesystem, and in release builds, where it is embedded. Files left out by `.gitignore` or `.ignore` files in the directory or its subdirectories are left out of both too, unless an `include` glob matches them. Hidden files and directories, whose names start with a dot, are left out of both, except for `.well-known`.
+///
+/// If `compress` is true, a gzipped copy of each embedded file is embedded alongside it, except for files that are already compressed or do not get smaller. If `compressed_only` is also true, files with a gzipped copy are embedded only gzipped, which keeps binaries small, and are decompressed the first time they are read. Only gzip is supported, so no Brotli copies are embedded and clients that accept only Brotli get the uncompressed file.
+struct Args {
+	path: syn::LitStr,
+	env: Option<syn::LitStr>,
+	features: Vec<(syn::LitStr, syn::LitStr)>,
+	compress: bool,
+	compressed_only: bool,
+	include: Vec<syn::LitStr>,
+	exclude: Vec<syn::LitStr>,
+}
+
+impl Parse for Args {
+	fn parse(input: ParseStream) -> syn::Result<Self> {
+		let path = input.parse()?;
+		let mut env = None;
+		let mut features = Vec::new();
+		let mut compress = false;
+		let mut compressed_only = false;
+		let mut include = Vec::new();
+		let mut exclude = Vec::new();
+		while !input.is_empty() {
+			input.parse::<Token![,]>()?;
+			if input.is_empty() {
+				break;
+			}
+			let key: syn::Ident = input.parse()?;
+			input.parse::<Token![=]>()?;
+			match key.to_string().as_str() {
+				"env" => {
+					env = Some(input.parse()?);
+				}
+				"features" => {
+					let content;
+					syn::braced!(content in input);
+					while !content.is_empty() {
+						let feature: syn::LitStr = content.parse()?;
+						content.parse::<Token![=>]>()?;
+						let path: syn::LitStr = content.parse()?;
+						features.push((feature, path));
+						if content.is_empty() {
+							break;
+						}
+						content.parse::<Token![,]>()?;
+					}
+				}
+				"compress" => {
+					compress = input.parse::<syn::LitBool>()?.value;
+				}
+				"compressed_only" => {
+					compressed_only = input.parse::<syn::LitBool>()?.value;
+				}
+				"include" => {
+					include.extend(parse_patterns(input)?);
+				}
+				"exclude" => {
+					exclude.extend(parse_patterns(input)?);
+				}
+				_ => return Err(syn::Error::new(key.span(), "unknown argument")),
+			}
+		}
+		Ok(Args {
+			path,
+			env,
+			features,
+			compress,
+			compressed_only,
+			include,
+			exclude,
+		})
+	}
+}
+
+fn parse_patterns(input: ParseStream) -> syn::Result<Vec<syn::LitStr>> {
+	let content;
+	syn::bracketed!(content in input);
+	let patterns = content.parse_terminated::<syn::LitStr, Token![,]>(|input| input.parse())?;
+	Ok(patterns.into_iter().collect())
+}
+
+/// The `include` and `exclude` globs.
+struct Filter<'a> {
+	include: &'a [syn::LitStr],
+	exclude: &'a [syn::LitStr],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Compression {
+	None,
+	/// Embed a gzipped copy of each file alongside it.
+	Alongside,
+	/// Embed only the gzipped copy of each file that has one.
+	Only,
+}
 
 pub fn include_dir(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenStream> {
-	let path: syn::LitStr = syn::parse2(input)?;
-	let path = Path::new(&path.value()).canonicalize().unwrap();
-	let path_string = path.display().to_string();
+	let args: Args = syn::parse2(input)?;
+	let algorithm =
+		hash_algorithm().map_err(|message| syn::Error::new(args.path.span(), message))?;
+	let compression = match (args.compress, args.compressed_only) {
+		(false, _) => Compression::None,
+		(true, false) => Compression::Alongside,
+		(true, true) => Compression::Only,
+	};
+	let filter = Filter {
+		include: &args.include,
+		exclude: &args.exclude,
+	};
+	let directory =
+		|path: &Path, span: Span| directory(path, span, &algorithm, compression, &filter);
+	// An environment variable set at compile time takes precedence over everything else.
+	if let Some(env) = &args.env {
+		if let Ok(path) = std::env::var(env.value()) {
+			return directory(Path::new(&path), env.span());
+		}
+	}
+	if args.features.is_empty() {
+		return directory(Path::new(&args.path.value()), args.path.span());
+	}
+	// Otherwise, select the directory for the first enabled feature, falling back to the default path.
+	let mut previous_features = Vec::new();
+	let mut variants = Vec::new();
+	for (feature, path) in args.features.iter() {
+		let directory = directory(Path::new(&path.value()), path.span())?;
+		variants.push(quote! {
+			#[cfg(all(feature = #feature, not(any(#(feature = #previous_features),*))))]
+			#directory
+		});
+		previous_features.push(feature);
+	}
+	let directory = directory(Path::new(&args.path.value()), args.path.span())?;
+	let code = quote! {{
+		#(#variants)*
+		#[cfg(not(any(#(feature = #previous_features),*)))]
+		#directory
+	}};
+	Ok(code)
+}
+
+fn directory(
+	path: &Path,
+	span: Span,
+	algorithm: &str,
+	compression: Compression,
+	filter: &Filter,
+) -> syn::Result<proc_macro2::TokenStream> {
+	let path = path.canonicalize().map_err(|error| {
+		let message = format!("failed to find the directory {}: {}", path.display(), error);
+		syn::Error::new(span, message)
+	})?;
+	let path_string = path_to_str(&path, span)?;
+	let include = filter.include;
+	let exclude = filter.exclude;
+	// The patterns were checked when embedding the directory, so building the filter cannot fail.
 	let fs_directory = quote! {{
 	  let path = std::path::PathBuf::from(#path_string);
-		let fs_directory = sunfish::include_dir::FsDirectory(path);
+		let filter = sunfish::include_dir::PathFilter::new(&path, &[#(#include),*], &[#(#exclude),*]).unwrap();
+		let fs_directory = sunfish::include_dir::FsDirectory(path, filter);
 		sunfish::include_dir::IncludeDir::Fs(fs_directory)
 	}};
-	let embedded_directory = embedded_directory(&path);
+	let embedded_directory = embedded_directory(&path, span, algorithm, compression, filter)?;
 	let embedded_directory = quote! {{
 		sunfish::include_dir::IncludeDir::Included(#embedded_directory)
 	}};
 	let code = quote! {{
+	  sunfish::include_dir::check_hash_algorithm(#algorithm);
 	  #[cfg(debug_assertions)]
 	  #fs_directory
 	  #[cfg(not(debug_assertions))]
@@ -25,51 +196,210 @@ pub fn include_dir(input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::
 	Ok(code)
 }
 
-fn embedded_directory(path: &Path) -> proc_macro2::TokenStream {
-	let mut absolute_paths: Vec<PathBuf> = WalkDir::new(&path)
-		.into_iter()
-		.filter_map(|entry| {
-			let entry = entry.unwrap();
-			let path = entry.path().to_owned();
-			let metadata = std::fs::metadata(&path).unwrap();
-			if metadata.is_file() {
-				Some(path)
-			} else {
-				None
-			}
-		})
-		.collect();
+fn embedded_directory(
+	path: &Path,
+	span: Span,
+	algorithm: &str,
+	compression: Compression,
+	filter: &Filter,
+) -> syn::Result<proc_macro2::TokenStream> {
+	// Exclude patterns are added to the overrides negated, which makes them ignore the files they match.
+	let mut overrides = OverrideBuilder::new(path);
+	for pattern in filter.include {
+		overrides
+			.add(&pattern.value())
+			.map_err(|error| syn::Error::new(pattern.span(), error.to_string()))?;
+	}
+	for pattern in filter.exclude {
+		overrides
+			.add(&format!("!{}", pattern.value()))
+			.map_err(|error| syn::Error::new(pattern.span(), error.to_string()))?;
+	}
+	let overrides = overrides
+		.build()
+		.map_err(|error| syn::Error::new(span, error.to_string()))?;
+	// Honor the `.ignore` and `.gitignore` files in the directory, but not ones outside it or git's global and repository excludes, which debug builds do not apply. Hidden files are filtered below so that `.well-known` is kept.
+	let walk = WalkBuilder::new(path)
+		.standard_filters(true)
+		.hidden(false)
+		.parents(false)
+		.git_global(false)
+		.git_exclude(false)
+		.require_git(false)
+		.overrides(overrides)
+		.filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.file_name()))
+		.build();
+	let mut absolute_paths: Vec<PathBuf> = Vec::new();
+	for entry in walk {
+		let entry = entry.map_err(|error| {
+			let message = format!(
+				"failed to read an entry while embedding directory {}: {}",
+				path.display(),
+				error
+			);
+			syn::Error::new(span, message)
+		})?;
+		let metadata = std::fs::metadata(entry.path()).map_err(|error| {
+			let message = format!(
+				"failed to read {} while embedding directory {}: {}",
+				entry.path().display(),
+				path.display(),
+				error
+			);
+			syn::Error::new(span, message)
+		})?;
+		if metadata.is_file() {
+			absolute_paths.push(entry.path().to_owned());
+		}
+	}
 	absolute_paths.sort();
-	let hashes = absolute_paths
-		.iter()
-		.map(|path| hash(std::fs::read(path).unwrap()));
-	let relative_paths = absolute_paths
-		.iter()
-		.map(|absolute_path| absolute_path.strip_prefix(&path).unwrap().to_owned());
-	let absolute_paths = absolute_paths
-		.iter()
-		.map(|path| path.to_str().unwrap().to_owned());
-	let relative_paths = relative_paths.map(|path| path.to_str().unwrap().to_owned());
-	quote! {{
+	// Included files are stamped with the build time, or SOURCE_DATE_EPOCH for reproducible builds.
+	let build_time = std::env::var("SOURCE_DATE_EPOCH")
+		.ok()
+		.and_then(|source_date_epoch| source_date_epoch.parse::<u64>().ok())
+		.unwrap_or_else(|| {
+			std::time::SystemTime::now()
+				.duration_since(std::time::UNIX_EPOCH)
+				.unwrap()
+				.as_secs()
+		});
+	let mut relative_paths = Vec::new();
+	let mut hashes = Vec::new();
+	let mut full_hashes = Vec::new();
+	let mut gzips = Vec::new();
+	let mut datas = Vec::new();
+	for absolute_path in absolute_paths.iter() {
+		let data = std::fs::read(absolute_path).map_err(|error| {
+			let message = format!(
+				"failed to read {} while embedding directory {}: {}",
+				absolute_path.display(),
+				path.display(),
+				error
+			);
+			syn::Error::new(span, message)
+		})?;
+		let gzip = if compression != Compression::None && is_compressible(absolute_path) {
+			gzip(&data)
+				.filter(|gzip| gzip.len() < data.len())
+				.map(|gzip| syn::LitByteStr::new(&gzip, span))
+		} else {
+			None
+		};
+		let absolute_path_string = path_to_str(absolute_path, span)?;
+		datas.push(match (&gzip, compression) {
+			(Some(_), Compression::Only) => {
+				let size = data.len();
+				quote! {{
+					// Include the file's bytes without using them so that rustc rebuilds when the file changes.
+					const _: &[u8] = include_bytes!(#absolute_path_string);
+					static DECOMPRESSED: sunfish::include_dir::OnceCell<Vec<u8>> =
+						sunfish::include_dir::OnceCell::new();
+					sunfish::include_dir::IncludedData::Gzip {
+						gzip: gzip.unwrap(),
+						size: #size,
+						decompressed: &DECOMPRESSED,
+					}
+				}}
+			}
+			_ => quote! {
+				sunfish::include_dir::IncludedData::Raw(include_bytes!(#absolute_path_string).as_ref())
+			},
+		});
+		gzips.push(match gzip {
+			Some(gzip) => quote! { Some(#gzip) },
+			None => quote! { None },
+		});
+		let full_hash = full_hash(algorithm, data);
+		hashes.push(full_hash[0..16].to_owned());
+		full_hashes.push(full_hash);
+		let relative_path = absolute_path.strip_prefix(path).map_err(|_| {
+			let message = format!(
+				"{} is not inside the directory {}",
+				absolute_path.display(),
+				path.display()
+			);
+			syn::Error::new(span, message)
+		})?;
+		relative_paths.push(path_to_str(relative_path, span)?.to_owned());
+	}
+	let code = quote! {{
 		let mut map = std::collections::BTreeMap::new();
 		#({
 			let path = std::path::Path::new(#relative_paths);
-			let data = include_bytes!(#absolute_paths);
+			// Files embedded only gzipped refer to the same bytes for their data.
+			let gzip: Option<&'static [u8]> = #gzips;
 			let file = sunfish::include_dir::IncludedFile {
-				data: data.as_ref(),
+				data: #datas,
 				hash: #hashes,
+				full_hash: #full_hashes,
+				gzip,
+				modified: #build_time,
 			};
 			map.insert(path, file);
 		})*
 		sunfish::include_dir::IncludedDirectory(map)
-	}}
+	}};
+	Ok(code)
+}
+
+/// Whether a file or directory is hidden. This matches `sunfish::include_dir::PathFilter`.
+fn is_hidden(file_name: &std::ffi::OsStr) -> bool {
+	let file_name = file_name.to_string_lossy();
+	file_name.starts_with('.') && file_name != ".well-known"
+}
+
+fn path_to_str(path: &Path, span: Span) -> syn::Result<&str> {
+	path.to_str().ok_or_else(|| {
+		let message = format!("the path {} is not valid UTF-8", path.display());
+		syn::Error::new(span, message)
+	})
+}
+
+/// Whether a file is worth compressing, judging by its extension. Images, fonts, media, and archives are already compressed.
+fn is_compressible(path: &Path) -> bool {
+	let extension = path
+		.extension()
+		.and_then(|extension| extension.to_str())
+		.map(|extension| extension.to_ascii_lowercase());
+	!matches!(
+		extension.as_deref(),
+		Some(
+			"avif"
+				| "br" | "gif"
+				| "gz" | "jpeg"
+				| "jpg" | "mp3"
+				| "mp4" | "ogg"
+				| "png" | "webm"
+				| "webp" | "woff"
+				| "woff2" | "zip"
+		)
+	)
+}
+
+fn gzip(data: &[u8]) -> Option<Vec<u8>> {
+	use std::io::Write;
+	let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+	encoder.write_all(data).ok()?;
+	encoder.finish().ok()
+}
+
+/// Read the hash algorithm from `SUNFISH_HASH_ALGORITHM`, which must match the value sunfish was compiled with.
+fn hash_algorithm() -> Result<String, String> {
+	let algorithm = std::env::var("SUNFISH_HASH_ALGORITHM").unwrap_or_else(|_| "sha256".to_owned());
+	match algorithm.as_str() {
+		"sha224" | "sha256" | "sha384" | "sha512" => Ok(algorithm),
+		_ => Err(format!(
+			"unsupported hash algorithm \"{}\" in SUNFISH_HASH_ALGORITHM",
+			algorithm
+		)),
+	}
 }
 
-pub fn hash(bytes: impl AsRef<[u8]>) -> String {
-	let mut hash: sha2::Sha256 = Digest::new();
-	hash.update(bytes);
-	let hash = hash.finalize();
-	let hash = hex::encode(hash);
-	let hash = &hash[0..16];
-	hash.to_owned()
+pub fn full_hash(algorithm: &str, bytes: impl AsRef<[u8]>) -> String {
+	match algorithm {
+		"sha224" => hex::encode(sha2::Sha224::digest(bytes)),
+		"sha384" => hex::encode(sha2::Sha384::digest(bytes)),
+		"sha512" => hex::encode(sha2::Sha512::digest(bytes)),
+		_ => hex::encode(sha2::Sha256::digest(bytes)),
+	}
 }
@@ -11,11 +11,11 @@ pub fn init(_input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenS
 	let routes_handler = routes_handler(&server_entries);
 	let routes = routes(&server_entries);
 	let code = quote! {{
-		sunfish::Sunfish {
-			output: sunfish::include_dir!(#output_path_string),
-			routes_handler: #routes_handler,
-			routes: #routes,
-		}
+		sunfish::Sunfish::new(
+			sunfish::include_dir!(#output_path_string),
+			#routes_handler,
+			#routes,
+		)
 	}};
 	Ok(code)
 }
@@ -24,6 +24,7 @@ pub fn init(_input: proc_macro2::TokenStream) -> syn::Result<proc_macro2::TokenS
 struct ServerEntry {
 	package_name: String,
 	path_with_placeholders: String,
+	client_package_name: Option<String>,
 }
 
 fn server_entries(routes_path: &Path) -> Vec<ServerEntry> {
@@ -37,24 +38,25 @@ fn server_entries(routes_path: &Path) -> Vec<ServerEntry> {
 		.unwrap()
 		.filter_map(Result::ok)
 		.map(|manifest_path| {
-			let manifest = std::fs::read_to_string(&manifest_path).unwrap();
-			let manifest: toml::Value = toml::from_str(&manifest).unwrap();
-			let package_name = manifest
-				.as_table()
-				.unwrap()
-				.get("package")
-				.unwrap()
-				.as_table()
-				.unwrap()
-				.get("name")
+			let package_name = read_package_name(&manifest_path);
+			let path_with_placeholders = path_with_placeholders(routes_path, &manifest_path);
+			// A client crate next to the server crate is the route's own client.
+			let client_manifest_path = manifest_path
+				.parent()
 				.unwrap()
-				.as_str()
+				.parent()
 				.unwrap()
-				.to_owned();
-			let path_with_placeholders = path_with_placeholders(routes_path, &manifest_path);
+				.join("client")
+				.join("Cargo.toml");
+			let client_package_name = if client_manifest_path.exists() {
+				Some(read_package_name(&client_manifest_path))
+			} else {
+				None
+			};
 			ServerEntry {
 				package_name,
 				path_with_placeholders,
+				client_package_name,
 			}
 		})
 		.collect::<Vec<_>>();
@@ -62,13 +64,30 @@ fn server_entries(routes_path: &Path) -> Vec<ServerEntry> {
 	entries
 }
 
+fn read_package_name(manifest_path: &Path) -> String {
+	let manifest = std::fs::read_to_string(manifest_path).unwrap();
+	let manifest: toml::Value = toml::from_str(&manifest).unwrap();
+	manifest
+		.as_table()
+		.unwrap()
+		.get("package")
+		.unwrap()
+		.as_table()
+		.unwrap()
+		.get("name")
+		.unwrap()
+		.as_str()
+		.unwrap()
+		.to_owned()
+}
+
 fn path_with_placeholders(routes_path: &Path, manifest_path: &Path) -> String {
 	let components = manifest_path
 		.parent()
 		.unwrap()
 		.parent()
 		.unwrap()
-		.strip_prefix(&routes_path)
+		.strip_prefix(routes_path)
 		.unwrap()
 		.components()
 		.map(|component| match component {
@@ -93,13 +112,13 @@ fn routes_handler(server_entries: &[ServerEntry]) -> proc_macro2::TokenStream {
 	let match_arms = server_entries.iter().map(|server_entry| {
 		let package_name = &server_entry.package_name;
 		let server_package_name_ident = format_ident!("{}", server_entry.package_name);
+		let path_with_placeholders = &server_entry.path_with_placeholders;
 		let path_components = server_entry
 			.path_with_placeholders
 			.split('/')
-			.into_iter()
 			.skip(1)
 			.map(|path_component| match path_component {
-				"_" => quote! { _ },
+				path_component if path_component.starts_with('_') => quote! { _ },
 				"index" => quote! { "" },
 				path_component => quote! { #path_component },
 			})
@@ -108,6 +127,8 @@ fn routes_handler(server_entries: &[ServerEntry]) -> proc_macro2::TokenStream {
 			#[cfg(feature = #package_name)]
 			[#(#path_components),*] => {
 				use futures::{Future, FutureExt, TryFutureExt};
+				let path_params = sunfish::PathParams::new(#path_with_placeholders, request.uri().path());
+				request.extensions_mut().insert(path_params);
 				#server_package_name_ident::init().handle(request).map_ok(|response| Some(response)).boxed()
 			}
 		}
@@ -134,10 +155,15 @@ fn routes(server_entries: &[ServerEntry]) -> proc_macro2::TokenStream {
 			let package_name = server_entry.package_name.to_owned();
 			let package_name_ident = format_ident!("{}", package_name);
 			let path_with_placeholders = &server_entry.path_with_placeholders;
+			let client_crate_name = match &server_entry.client_package_name {
+				Some(client_package_name) => quote! { Some(#client_package_name) },
+				None => quote! { None },
+			};
 			quote! {
 				sunfish::RouteInitializer {
 					path_with_placeholders: #path_with_placeholders.to_owned(),
 					init: #package_name_ident::init,
+					client_crate_name: #client_crate_name,
 				}
 			}
 		})
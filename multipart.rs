@@ -0,0 +1,45 @@
+use anyhow::{anyhow, Result};
+pub use multer::{Field, Multipart};
+
+/// Limits on the size of a `multipart/form-data` body. Exceeding a limit makes reading the offending field fail.
+#[derive(Clone, Debug, Default)]
+pub struct MultipartLimits {
+	/// The maximum size in bytes of any one part.
+	pub per_part: Option<u64>,
+	/// The maximum size in bytes of the whole body.
+	pub total: Option<u64>,
+}
+
+/// Parse the body of a `multipart/form-data` request. The body is taken from the request and read incrementally as each field's chunks are consumed, so uploads are never buffered whole in memory.
+///
+/// ```ignore
+/// let mut multipart = sunfish::multipart::multipart(request, MultipartLimits::default())?;
+/// while let Some(mut field) = multipart.next_field().await? {
+///     let name = field.name().map(ToOwned::to_owned);
+///     let file_name = field.file_name().map(ToOwned::to_owned);
+///     while let Some(chunk) = field.chunk().await? {
+///         // Write the chunk somewhere.
+///     }
+/// }
+/// ```
+pub fn multipart(
+	request: &mut http::Request<hyper::Body>,
+	limits: MultipartLimits,
+) -> Result<Multipart<'static>> {
+	let content_type = request
+		.headers()
+		.get(http::header::CONTENT_TYPE)
+		.ok_or_else(|| anyhow!("the request has no content type"))?
+		.to_str()?;
+	let boundary = multer::parse_boundary(content_type)?;
+	let mut size_limit = multer::SizeLimit::new();
+	if let Some(per_part) = limits.per_part {
+		size_limit = size_limit.per_field(per_part);
+	}
+	if let Some(total) = limits.total {
+		size_limit = size_limit.whole_stream(total);
+	}
+	let constraints = multer::Constraints::new().size_limit(size_limit);
+	let body = std::mem::take(request.body_mut());
+	Ok(Multipart::with_constraints(body, boundary, constraints))
+}
@@ -0,0 +1,82 @@
+/// A Network Error Logging policy, sent with every response as `NEL` and `Report-To` headers so that browsers report DNS, TLS, and connection failures to a collector.
+#[derive(Clone, Debug)]
+pub struct NetworkErrorLogging {
+	/// The name of the reporting group.
+	pub group: String,
+	/// The URL of the collector that receives the reports.
+	pub endpoint: String,
+	/// How long in seconds browsers apply the policy.
+	pub max_age: u64,
+	/// Whether the policy also applies to subdomains.
+	pub include_subdomains: bool,
+	/// The fraction of successful requests to report, between `0.0` and `1.0`. Browsers default to `0.0`.
+	pub success_fraction: Option<f64>,
+	/// The fraction of failed requests to report, between `0.0` and `1.0`. Browsers default to `1.0`.
+	pub failure_fraction: Option<f64>,
+}
+
+impl NetworkErrorLogging {
+	pub fn new(
+		group: impl Into<String>,
+		endpoint: impl Into<String>,
+		max_age: u64,
+	) -> NetworkErrorLogging {
+		NetworkErrorLogging {
+			group: group.into(),
+			endpoint: endpoint.into(),
+			max_age,
+			include_subdomains: false,
+			success_fraction: None,
+			failure_fraction: None,
+		}
+	}
+
+	/// Add the `NEL` and `Report-To` headers to `headers`, unless the response already has them.
+	pub(crate) fn apply(&self, headers: &mut http::HeaderMap) {
+		if headers.contains_key("nel") {
+			return;
+		}
+		let group = json_string(&self.group);
+		let mut report_to = format!(
+			r#"{{"group":{},"max_age":{},"endpoints":[{{"url":{}}}]"#,
+			group,
+			self.max_age,
+			json_string(&self.endpoint)
+		);
+		let mut nel = format!(r#"{{"report_to":{},"max_age":{}"#, group, self.max_age);
+		if self.include_subdomains {
+			report_to.push_str(r#","include_subdomains":true"#);
+			nel.push_str(r#","include_subdomains":true"#);
+		}
+		if let Some(success_fraction) = self.success_fraction {
+			nel.push_str(&format!(r#","success_fraction":{}"#, success_fraction));
+		}
+		if let Some(failure_fraction) = self.failure_fraction {
+			nel.push_str(&format!(r#","failure_fraction":{}"#, failure_fraction));
+		}
+		report_to.push('}');
+		nel.push('}');
+		if let (Ok(report_to), Ok(nel)) = (
+			http::HeaderValue::from_str(&report_to),
+			http::HeaderValue::from_str(&nel),
+		) {
+			headers.insert("report-to", report_to);
+			headers.insert("nel", nel);
+		}
+	}
+}
+
+fn json_string(value: &str) -> String {
+	let mut json = String::with_capacity(value.len() + 2);
+	json.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+	json
+}
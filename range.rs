@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Result};
+use hyper::body::HttpBody;
+
+/// Serve the part of a successful response to a `GET` request that the request's `Range` header asks for. This applies to any response whose body has a known length, such as assets, static pages, and the SPA fallback. Responses with a known length are marked with `Accept-Ranges: bytes`. Only single ranges are supported, so requests for several ranges get the full response.
+pub async fn apply_range(
+	method: &http::Method,
+	request_headers: &http::HeaderMap,
+	response: http::Response<hyper::Body>,
+) -> Result<http::Response<hyper::Body>> {
+	if response.status() != http::StatusCode::OK {
+		return Ok(response);
+	}
+	// Streamed bodies have no exact size, so fall back to the Content-Length header.
+	let content_length = response
+		.headers()
+		.get(http::header::CONTENT_LENGTH)
+		.and_then(|content_length| content_length.to_str().ok())
+		.and_then(|content_length| content_length.parse().ok());
+	let len = match response.body().size_hint().exact().or(content_length) {
+		Some(len) => len,
+		None => return Ok(response),
+	};
+	let (mut parts, body) = response.into_parts();
+	parts.headers.insert(
+		http::header::ACCEPT_RANGES,
+		http::HeaderValue::from_static("bytes"),
+	);
+	let range = match request_headers
+		.get(http::header::RANGE)
+		.and_then(|range| range.to_str().ok())
+	{
+		Some(range)
+			if method == http::Method::GET && if_range_matches(request_headers, &parts.headers) =>
+		{
+			range
+		}
+		_ => return Ok(http::Response::from_parts(parts, body)),
+	};
+	let (start, end) = match parse_range(range, len) {
+		Some(Some(range)) => range,
+		Some(None) => {
+			parts.status = http::StatusCode::RANGE_NOT_SATISFIABLE;
+			parts.headers.insert(
+				http::header::CONTENT_RANGE,
+				http::HeaderValue::from_str(&format!("bytes */{}", len))?,
+			);
+			parts.headers.insert(
+				http::header::CONTENT_LENGTH,
+				http::HeaderValue::from_static("0"),
+			);
+			return Ok(http::Response::from_parts(parts, hyper::Body::empty()));
+		}
+		None => return Ok(http::Response::from_parts(parts, body)),
+	};
+	// Slice the body as it streams, so large streamed bodies are not buffered and reading stops at the end of the range.
+	let body = slice_body(body, start, end);
+	parts.status = http::StatusCode::PARTIAL_CONTENT;
+	parts.headers.insert(
+		http::header::CONTENT_RANGE,
+		http::HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len))?,
+	);
+	parts.headers.insert(
+		http::header::CONTENT_LENGTH,
+		http::HeaderValue::from(end - start + 1),
+	);
+	Ok(http::Response::from_parts(parts, body))
+}
+
+/// Stream the bytes of `body` from `start` to `end` inclusive.
+fn slice_body(body: hyper::Body, start: u64, end: u64) -> hyper::Body {
+	let chunks = futures::stream::try_unfold((body, 0), move |(mut body, mut offset)| async move {
+		loop {
+			if offset > end {
+				return Ok(None);
+			}
+			let chunk = match body.data().await {
+				Some(chunk) => chunk?,
+				None => return Err(anyhow!("the body ended before the end of the range")),
+			};
+			let chunk_start = offset;
+			offset += chunk.len() as u64;
+			if offset <= start {
+				continue;
+			}
+			let from = start.saturating_sub(chunk_start) as usize;
+			let to = (end + 1 - chunk_start).min(chunk.len() as u64) as usize;
+			return Ok(Some((chunk.slice(from..to), (body, offset))));
+		}
+	});
+	hyper::Body::wrap_stream(chunks)
+}
+
+/// Whether the request's `If-Range` header, if any, matches the response's `ETag`. Only entity tags are compared. An `If-Range` date never matches, so the full response is sent.
+fn if_range_matches(request_headers: &http::HeaderMap, response_headers: &http::HeaderMap) -> bool {
+	let if_range = match request_headers.get(http::header::IF_RANGE) {
+		Some(if_range) => if_range,
+		None => return true,
+	};
+	let etag = match response_headers.get(http::header::ETAG) {
+		Some(etag) => etag,
+		None => return false,
+	};
+	let if_range = if_range.as_bytes();
+	// The strong comparison function requires both tags to be strong.
+	!if_range.starts_with(b"W/")
+		&& !etag.as_bytes().starts_with(b"W/")
+		&& trim_quotes(if_range) == trim_quotes(etag.as_bytes())
+}
+
+fn trim_quotes(etag: &[u8]) -> &[u8] {
+	let etag = etag.strip_prefix(b"\"").unwrap_or(etag);
+	etag.strip_suffix(b"\"").unwrap_or(etag)
+}
+
+/// Parse a `Range` header for a body of `len` bytes. Returns `None` if the header should be ignored, `Some(None)` if the range cannot be satisfied, and otherwise the inclusive start and end of the range.
+fn parse_range(range: &str, len: u64) -> Option<Option<(u64, u64)>> {
+	let range = range.trim().strip_prefix("bytes=")?;
+	if range.contains(',') {
+		return None;
+	}
+	let (start, end) = range.trim().split_once('-')?;
+	let (start, end) = (start.trim(), end.trim());
+	let range = if start.is_empty() {
+		// A suffix range asks for the last bytes of the body.
+		let suffix_len: u64 = end.parse().ok()?;
+		if suffix_len == 0 || len == 0 {
+			return Some(None);
+		}
+		(len.saturating_sub(suffix_len), len - 1)
+	} else {
+		let start: u64 = start.parse().ok()?;
+		let end = if end.is_empty() {
+			u64::MAX
+		} else {
+			end.parse().ok()?
+		};
+		if end < start {
+			return None;
+		}
+		if start >= len {
+			return Some(None);
+		}
+		(start, end.min(len - 1))
+	};
+	Some(Some(range))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BODY: &[u8] = b"0123456789";
+
+	async fn respond(range: Option<&str>, if_range: Option<&str>) -> http::Response<hyper::Body> {
+		let mut request_headers = http::HeaderMap::new();
+		if let Some(range) = range {
+			request_headers.insert(http::header::RANGE, range.parse().unwrap());
+		}
+		if let Some(if_range) = if_range {
+			request_headers.insert(http::header::IF_RANGE, if_range.parse().unwrap());
+		}
+		let response = http::Response::builder()
+			.header(http::header::ETAG, "\"abc\"")
+			.body(hyper::Body::from(BODY))
+			.unwrap();
+		apply_range(&http::Method::GET, &request_headers, response)
+			.await
+			.unwrap()
+	}
+
+	async fn body(response: http::Response<hyper::Body>) -> Vec<u8> {
+		hyper::body::to_bytes(response.into_body())
+			.await
+			.unwrap()
+			.to_vec()
+	}
+
+	#[test]
+	fn parse_range_table() {
+		let cases = [
+			("bytes=0-4", Some(Some((0, 4)))),
+			("bytes=5-", Some(Some((5, 9)))),
+			("bytes=5-100", Some(Some((5, 9)))),
+			("bytes=-3", Some(Some((7, 9)))),
+			("bytes=-100", Some(Some((0, 9)))),
+			("bytes=-0", Some(None)),
+			("bytes=10-", Some(None)),
+			("bytes=20-30", Some(None)),
+			("bytes=4-2", None),
+			("bytes=0-1,3-4", None),
+			("items=0-4", None),
+			("bytes=a-b", None),
+		];
+		for (range, expected) in cases {
+			assert_eq!(parse_range(range, 10), expected, "{}", range);
+		}
+	}
+
+	#[tokio::test]
+	async fn single_ranges_are_partial_content() {
+		let response = respond(Some("bytes=2-5"), None).await;
+		assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			response.headers()[http::header::CONTENT_RANGE],
+			"bytes 2-5/10"
+		);
+		assert_eq!(response.headers()[http::header::CONTENT_LENGTH], "4");
+		assert_eq!(body(response).await, b"2345");
+	}
+
+	#[tokio::test]
+	async fn suffix_ranges_are_the_end_of_the_body() {
+		let response = respond(Some("bytes=-3"), None).await;
+		assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			response.headers()[http::header::CONTENT_RANGE],
+			"bytes 7-9/10"
+		);
+		assert_eq!(body(response).await, b"789");
+	}
+
+	#[tokio::test]
+	async fn multiple_ranges_get_the_full_response() {
+		let response = respond(Some("bytes=0-1,4-5"), None).await;
+		assert_eq!(response.status(), http::StatusCode::OK);
+		assert_eq!(response.headers()[http::header::ACCEPT_RANGES], "bytes");
+		assert_eq!(body(response).await, BODY);
+	}
+
+	#[tokio::test]
+	async fn unsatisfiable_ranges_are_416() {
+		let response = respond(Some("bytes=10-20"), None).await;
+		assert_eq!(response.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+		assert_eq!(
+			response.headers()[http::header::CONTENT_RANGE],
+			"bytes */10"
+		);
+		assert!(body(response).await.is_empty());
+	}
+
+	#[tokio::test]
+	async fn if_range_must_match_a_strong_etag() {
+		let response = respond(Some("bytes=0-1"), Some("\"abc\"")).await;
+		assert_eq!(response.status(), http::StatusCode::PARTIAL_CONTENT);
+		let response = respond(Some("bytes=0-1"), Some("\"other\"")).await;
+		assert_eq!(response.status(), http::StatusCode::OK);
+		let response = respond(Some("bytes=0-1"), Some("W/\"abc\"")).await;
+		assert_eq!(response.status(), http::StatusCode::OK);
+	}
+
+	#[tokio::test]
+	async fn sliced_bodies_span_chunks() {
+		let chunks = ["012", "345", "6789"]
+			.iter()
+			.map(|chunk| Ok::<_, std::io::Error>(*chunk))
+			.collect::<Vec<_>>();
+		let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+		assert_eq!(
+			hyper::body::to_bytes(slice_body(body, 2, 7)).await.unwrap(),
+			"234567"
+		);
+	}
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+
+/// Build a response with `status` and `body`.
+pub fn respond(
+	status: http::StatusCode,
+	body: impl Into<hyper::Body>,
+) -> http::Response<hyper::Body> {
+	let mut response = http::Response::new(body.into());
+	*response.status_mut() = status;
+	response
+}
+
+/// Build a response with `status`, `headers`, and `body`.
+pub fn respond_with_headers(
+	status: http::StatusCode,
+	headers: http::HeaderMap,
+	body: impl Into<hyper::Body>,
+) -> http::Response<hyper::Body> {
+	let mut response = respond(status, body);
+	*response.headers_mut() = headers;
+	response
+}
+
+/// Build a `200` response with an HTML body.
+pub fn html(body: impl Into<hyper::Body>) -> http::Response<hyper::Body> {
+	with_content_type(
+		respond(http::StatusCode::OK, body),
+		"text/html; charset=utf-8",
+	)
+}
+
+/// Build a `200` response with a plain text body.
+pub fn text(body: impl Into<hyper::Body>) -> http::Response<hyper::Body> {
+	with_content_type(
+		respond(http::StatusCode::OK, body),
+		"text/plain; charset=utf-8",
+	)
+}
+
+/// Build a `404` response with a plain text body.
+pub fn not_found() -> http::Response<hyper::Body> {
+	with_content_type(
+		respond(http::StatusCode::NOT_FOUND, "not found"),
+		"text/plain; charset=utf-8",
+	)
+}
+
+/// Build a `302 Found` response that redirects to `location`. Returns an error if `location` is not a valid header value.
+pub fn redirect(location: &str) -> Result<http::Response<hyper::Body>> {
+	let mut response = respond(http::StatusCode::FOUND, hyper::Body::empty());
+	let location = http::HeaderValue::from_str(location)?;
+	response
+		.headers_mut()
+		.insert(http::header::LOCATION, location);
+	Ok(response)
+}
+
+fn with_content_type(
+	mut response: http::Response<hyper::Body>,
+	content_type: &'static str,
+) -> http::Response<hyper::Body> {
+	response.headers_mut().insert(
+		http::header::CONTENT_TYPE,
+		http::HeaderValue::from_static(content_type),
+	);
+	response
+}
@@ -0,0 +1,201 @@
+use crate::{internal_server_error, response::not_found, Route, Sunfish};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use hyper::body::HttpBody;
+use std::{
+	convert::Infallible,
+	net::SocketAddr,
+	sync::Arc,
+	task::{Context, Poll},
+};
+
+type Fallback = dyn Fn(http::Request<hyper::Body>) -> http::Response<hyper::Body> + Send + Sync;
+
+/// A `hyper::service::Service`, which is the same trait as `tower::Service`, that handles requests with a `Sunfish`. Requests that no page or asset matches are passed to the fallback if there is one, and otherwise get the same `404` as `Sunfish::respond` sends.
+#[derive(Clone)]
+pub struct SunfishService {
+	sunfish: Arc<Sunfish>,
+	fallback: Option<Arc<Fallback>>,
+}
+
+impl SunfishService {
+	pub fn new(sunfish: Arc<Sunfish>) -> SunfishService {
+		SunfishService {
+			sunfish,
+			fallback: None,
+		}
+	}
+
+	/// Respond to requests that no page or asset matches with `fallback` instead of a `404`.
+	pub fn with_fallback(
+		mut self,
+		fallback: impl Fn(http::Request<hyper::Body>) -> http::Response<hyper::Body>
+			+ Send
+			+ Sync
+			+ 'static,
+	) -> SunfishService {
+		self.fallback = Some(Arc::new(fallback));
+		self
+	}
+}
+
+impl hyper::service::Service<http::Request<hyper::Body>> for SunfishService {
+	type Response = http::Response<hyper::Body>;
+	type Error = Infallible;
+	type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, mut request: http::Request<hyper::Body>) -> Self::Future {
+		let sunfish = self.sunfish.clone();
+		let fallback = self.fallback.clone();
+		Box::pin(async move {
+			let response = match sunfish.handle(&mut request).await {
+				Ok(Some(response)) => response,
+				Ok(None) => match fallback {
+					Some(fallback) => fallback(request),
+					None => sunfish.not_found_response(request.uri().path()),
+				},
+				Err(error) => sunfish.error_response(request.uri().path(), &error),
+			};
+			Ok(response)
+		})
+	}
+}
+
+impl Sunfish {
+	/// Handle a request, responding with a `404` if no page or asset matches it and a `500` if handling it fails, using the not found and error handlers if they are set.
+	pub async fn respond(
+		&self,
+		mut request: http::Request<hyper::Body>,
+	) -> http::Response<hyper::Body> {
+		match self.handle(&mut request).await {
+			Ok(Some(response)) => response,
+			Ok(None) => self.not_found_response(request.uri().path()),
+			Err(error) => self.error_response(request.uri().path(), &error),
+		}
+	}
+
+	/// Respond to a request for `path` that no page or asset matches, with the page from the not found handler if there is one.
+	pub(crate) fn not_found_response(&self, path: &str) -> http::Response<hyper::Body> {
+		let not_found_handler = match &self.not_found_handler {
+			Some(not_found_handler) => not_found_handler,
+			None => return not_found(),
+		};
+		match not_found_handler(path.to_owned()) {
+			Ok(html) => http::Response::builder()
+				.status(http::StatusCode::NOT_FOUND)
+				.header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+				.body(hyper::Body::from(html))
+				.unwrap(),
+			Err(error) => {
+				tracing::error!(?error, "failed to render the not found page");
+				not_found()
+			}
+		}
+	}
+
+	/// Respond to a request for `path` whose handling failed with `error`, with the page from the error handler if there is one.
+	pub(crate) fn error_response(
+		&self,
+		path: &str,
+		error: &anyhow::Error,
+	) -> http::Response<hyper::Body> {
+		tracing::error!(?error, "failed to handle request");
+		match &self.error_handler {
+			Some(error_handler) => http::Response::builder()
+				.status(http::StatusCode::INTERNAL_SERVER_ERROR)
+				.header(http::header::CONTENT_TYPE, "text/html; charset=utf-8")
+				.body(hyper::Body::from(error_handler(path.to_owned(), error)))
+				.unwrap(),
+			None => internal_server_error(),
+		}
+	}
+
+	/// Convert this instance into a `SunfishService` to use it with hyper or in a tower stack.
+	pub fn into_service(self) -> SunfishService {
+		SunfishService::new(Arc::new(self))
+	}
+
+	/// Serve this instance on `addr` until the server fails.
+	pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+		let service = self.into_service();
+		let service = hyper::service::make_service_fn(|_| {
+			let service = service.clone();
+			async move { Ok::<_, Infallible>(service) }
+		});
+		hyper::Server::try_bind(&addr)?.serve(service).await?;
+		Ok(())
+	}
+
+	/// Resolve and render `path` exactly as a `GET` request for it would be handled, discarding the body. This populates any caches serving the path would, so it can be used to warm a freshly started server, for example with the paths from `static_urls`. Returns the response status, or `None` if nothing matched the path.
+	pub async fn warm_path(&self, path: &str) -> Result<Option<http::StatusCode>> {
+		let mut request = http::Request::builder()
+			.method(http::Method::GET)
+			.uri(path)
+			.body(hyper::Body::empty())?;
+		let response = match self.handle(&mut request).await? {
+			Some(response) => response,
+			None => return Ok(None),
+		};
+		let status = response.status();
+		let mut body = response.into_body();
+		while let Some(chunk) = body.data().await {
+			chunk?;
+		}
+		Ok(Some(status))
+	}
+
+	/// Handle a request with `method` for `path` through the full `handle` pipeline and return the response's status, headers, and body, for example to snapshot rendered pages in tests. Returns `None` if nothing matched the path.
+	pub async fn render_to_bytes(
+		&self,
+		method: http::Method,
+		path: &str,
+	) -> Result<Option<(http::StatusCode, http::HeaderMap, Vec<u8>)>> {
+		let mut request = http::Request::builder()
+			.method(method)
+			.uri(path)
+			.body(hyper::Body::empty())?;
+		let response = match self.handle(&mut request).await? {
+			Some(response) => response,
+			None => return Ok(None),
+		};
+		let (parts, body) = response.into_parts();
+		let body = hyper::body::to_bytes(body).await?;
+		Ok(Some((parts.status, parts.headers, body.to_vec())))
+	}
+
+	/// Get the paths of every static page and every file in the output.
+	pub fn static_urls(&self) -> Vec<String> {
+		let mut urls = self.static_page_paths();
+		for path in self.output.paths() {
+			if let Some(path) = path.to_str() {
+				urls.push(format!("/{}", path));
+			}
+		}
+		urls
+	}
+
+	/// Get the paths of every static page and every prerendered dynamic page, expanding routes with placeholders using their `paths`.
+	pub(crate) fn static_page_paths(&self) -> Vec<String> {
+		let mut page_paths = Vec::new();
+		for route in self.routes.iter() {
+			match (route.init)() {
+				Route::Static { paths, .. } => match paths {
+					Some(paths) => page_paths.extend(paths()),
+					None => page_paths.push(route.path_with_placeholders.clone()),
+				},
+				Route::Dynamic {
+					prerender: Some(prerender),
+					..
+				} => page_paths.extend((prerender.paths)()),
+				Route::Dynamic {
+					prerender: None, ..
+				} => {}
+			}
+		}
+		page_paths
+	}
+}
@@ -1,7 +1,11 @@
 use futures_batch::ChunksTimeoutStreamExt;
 use notify::Watcher;
-use std::{convert::Infallible, path::PathBuf, sync::Arc};
-use tokio::sync::{Mutex, Notify};
+use std::{
+	convert::Infallible,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+use tokio::sync::{broadcast, Mutex, Notify};
 use tokio_stream::StreamExt;
 use which::which;
 
@@ -13,6 +17,35 @@ pub struct Config {
 	pub watch_paths: Vec<PathBuf>,
 	pub ignore_paths: Vec<PathBuf>,
 	pub command: String,
+	/// Whether to reload pages in the browser after each successful rebuild. When enabled, a script is injected into HTML responses that listens for reload events, and pages only swap their stylesheets if only CSS files changed.
+	pub live_reload: bool,
+}
+
+/// The path of the server-sent events endpoint that pushes reload events to pages.
+const LIVE_RELOAD_PATH: &str = "/_sunfish/live_reload";
+
+/// The script injected into HTML responses when live reload is enabled.
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+	let source = new EventSource("/_sunfish/live_reload");
+	source.onmessage = function (event) {
+		if (event.data === "css") {
+			for (let link of document.querySelectorAll('link[rel="stylesheet"]')) {
+				let url = new URL(link.href);
+				url.searchParams.set("live_reload", Date.now());
+				link.href = url.toString();
+			}
+		} else {
+			location.reload();
+		}
+	};
+})();
+</script>"#;
+
+#[derive(Clone, Copy)]
+enum Reload {
+	Page,
+	Css,
 }
 
 pub async fn run(config: Config) {
@@ -24,6 +57,7 @@ pub async fn run(config: Config) {
 		watch_paths,
 		ignore_paths,
 		command,
+		live_reload,
 	} = config;
 	let addr = std::net::SocketAddr::new(host, port);
 	let child_addr = std::net::SocketAddr::new(child_host, child_port);
@@ -45,12 +79,14 @@ pub async fn run(config: Config) {
 		},
 	}
 	let state: Arc<Mutex<State>> = Arc::new(Mutex::new(State::Ground));
+	let (reload_tx, _) = broadcast::channel::<Reload>(16);
 	let (watch_events_tx, watch_events_rx) = tokio::sync::mpsc::unbounded_channel();
-	watch_events_tx.send(()).unwrap();
+	watch_events_tx.send(Vec::new()).unwrap();
 
 	// Run the file watcher.
-	let mut watcher = notify::recommended_watcher(move |_: notify::Result<notify::Event>| {
-		watch_events_tx.send(()).unwrap();
+	let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+		let paths = event.map(|event| event.paths).unwrap_or_default();
+		watch_events_tx.send(paths).unwrap();
 	})
 	.unwrap();
 	let mut walk_builder = ignore::WalkBuilder::new(watch_paths.first().unwrap());
@@ -75,11 +111,19 @@ pub async fn run(config: Config) {
 
 	tokio::spawn({
 		let state = state.clone();
+		let reload_tx = reload_tx.clone();
 		async move {
 			let mut watch_events =
 				tokio_stream::wrappers::UnboundedReceiverStream::new(watch_events_rx)
 					.chunks_timeout(1_000_000, std::time::Duration::from_millis(10));
-			while watch_events.next().await.is_some() {
+			while let Some(watch_events) = watch_events.next().await {
+				// Only swap stylesheets if every changed path is a CSS file.
+				let paths = watch_events.into_iter().flatten().collect::<Vec<_>>();
+				let reload = if !paths.is_empty() && paths.iter().all(|path| is_css(path)) {
+					Reload::Css
+				} else {
+					Reload::Page
+				};
 				// Kill the previous child process if any.
 				if let State::Running { child } = &mut *state.lock().await {
 					let mut child = child.take().unwrap();
@@ -91,25 +135,25 @@ pub async fn run(config: Config) {
 				let sh = which("sh").unwrap();
 				let child = std::process::Command::new(sh)
 					.args(vec!["-c", &command])
-					.env("HOST", &child_host.to_string())
-					.env("PORT", &child_port.to_string())
+					.env("HOST", child_host.to_string())
+					.env("PORT", child_port.to_string())
 					.spawn()
 					.unwrap();
 				*state.lock().await = State::Building {
 					notify: notify.clone(),
 					child: Some(child),
 				};
-				loop {
+				let started = loop {
 					tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 					if let State::Building { child, .. } = &mut *state.lock().await {
 						if let Ok(Some(_)) | Err(_) = child.as_mut().unwrap().try_wait() {
-							break;
+							break false;
 						}
 					}
 					if tokio::net::TcpStream::connect(&child_addr).await.is_ok() {
-						break;
+						break true;
 					}
-				}
+				};
 				let child = if let State::Building { child, .. } = &mut *state.lock().await {
 					child.take().unwrap()
 				} else {
@@ -117,12 +161,21 @@ pub async fn run(config: Config) {
 				};
 				*state.lock().await = State::Running { child: Some(child) };
 				notify.notify_waiters();
+				// Reload pages if the rebuild succeeded. Sending fails if no pages are listening, which is fine.
+				if started {
+					reload_tx.send(reload).ok();
+				}
 			}
 		}
 	});
 
 	// Handle requests by waiting for a build to finish if one is in progress, then proxying the request to the child process.
-	let handler = move |state: Arc<Mutex<State>>, mut request: http::Request<hyper::Body>| async move {
+	let handler = move |state: Arc<Mutex<State>>,
+	                    reload_tx: broadcast::Sender<Reload>,
+	                    mut request: http::Request<hyper::Body>| async move {
+		if live_reload && request.uri().path() == LIVE_RELOAD_PATH {
+			return live_reload_events(reload_tx.subscribe());
+		}
 		let notify = if let State::Building { notify, .. } = &mut *state.lock().await {
 			Some(notify.clone())
 		} else {
@@ -139,28 +192,103 @@ pub async fn run(config: Config) {
 			.path_and_query(request.uri().path_and_query().unwrap().clone())
 			.build()
 			.unwrap();
-		hyper::Client::new()
-			.request(request)
-			.await
-			.unwrap_or_else(|_| {
-				http::Response::builder()
-					.status(http::StatusCode::SERVICE_UNAVAILABLE)
-					.body(hyper::Body::from("service unavailable"))
-					.unwrap()
-			})
+		let response = match hyper::Client::new().request(request).await {
+			Ok(response) => response,
+			Err(_) => return service_unavailable(),
+		};
+		if live_reload {
+			inject_live_reload_script(response).await
+		} else {
+			response
+		}
 	};
 
 	// Start the server.
 	let service = hyper::service::make_service_fn(|_| {
 		let state = state.clone();
+		let reload_tx = reload_tx.clone();
 		async move {
 			Ok::<_, Infallible>(hyper::service::service_fn(
 				move |request: http::Request<hyper::Body>| {
 					let state = state.clone();
-					async move { Ok::<_, Infallible>(handler(state, request).await) }
+					let reload_tx = reload_tx.clone();
+					async move { Ok::<_, Infallible>(handler(state, reload_tx, request).await) }
 				},
 			))
 		}
 	});
 	hyper::Server::bind(&addr).serve(service).await.unwrap();
 }
+
+fn service_unavailable() -> http::Response<hyper::Body> {
+	http::Response::builder()
+		.status(http::StatusCode::SERVICE_UNAVAILABLE)
+		.body(hyper::Body::from("service unavailable"))
+		.unwrap()
+}
+
+fn is_css(path: &Path) -> bool {
+	path.extension()
+		.map(|extension| extension.eq_ignore_ascii_case("css"))
+		.unwrap_or(false)
+}
+
+/// Respond with a stream of server-sent events, one for each reload.
+fn live_reload_events(mut reload_rx: broadcast::Receiver<Reload>) -> http::Response<hyper::Body> {
+	let (mut sender, body) = hyper::Body::channel();
+	tokio::spawn(async move {
+		loop {
+			let reload = match reload_rx.recv().await {
+				Ok(reload) => reload,
+				Err(broadcast::error::RecvError::Lagged(_)) => Reload::Page,
+				Err(broadcast::error::RecvError::Closed) => break,
+			};
+			let data = match reload {
+				Reload::Page => "page",
+				Reload::Css => "css",
+			};
+			// Sending fails once the page disconnects.
+			let event = format!("data: {}\n\n", data);
+			if sender.send_data(event.into()).await.is_err() {
+				break;
+			}
+		}
+	});
+	http::Response::builder()
+		.header(http::header::CONTENT_TYPE, "text/event-stream")
+		.header(http::header::CACHE_CONTROL, "no-cache")
+		.body(body)
+		.unwrap()
+}
+
+/// Insert the live reload script before the closing body tag of a successful HTML response, or at its end if there is none. Other responses and encoded responses are passed through unchanged.
+async fn inject_live_reload_script(
+	response: http::Response<hyper::Body>,
+) -> http::Response<hyper::Body> {
+	let is_html = response
+		.headers()
+		.get(http::header::CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok())
+		.map(|content_type| content_type.starts_with("text/html"))
+		.unwrap_or(false);
+	let is_encoded = response
+		.headers()
+		.contains_key(http::header::CONTENT_ENCODING);
+	if response.status() != http::StatusCode::OK || !is_html || is_encoded {
+		return response;
+	}
+	let (mut parts, body) = response.into_parts();
+	let body = match hyper::body::to_bytes(body).await {
+		Ok(body) => body,
+		Err(_) => return service_unavailable(),
+	};
+	let body = String::from_utf8_lossy(&body);
+	let body = match body.rfind("</body>") {
+		Some(index) => format!("{}{}{}", &body[..index], LIVE_RELOAD_SCRIPT, &body[index..]),
+		None => format!("{}{}", body, LIVE_RELOAD_SCRIPT),
+	};
+	// The body changed, so its length and entity tag no longer apply.
+	parts.headers.remove(http::header::CONTENT_LENGTH);
+	parts.headers.remove(http::header::ETAG);
+	http::Response::from_parts(parts, hyper::Body::from(body))
+}